@@ -4,14 +4,15 @@ extern crate criterion;
 use criterion::{BatchSize, Criterion};
 use crossbeam::queue::{ArrayQueue, SegQueue};
 use crossbeam::scope;
-use ripstruct::SegBuffer;
+use ripstruct::{SegBuffer, SegBufferMpmc};
 
 const AMOUNT: usize = 1_000;
+const LARGE_SEGMENT: usize = 262_144;
 
 fn seg_buffer_push_single_thread(c: &mut Criterion) {
     c.bench_function("buffer_push_single_thread", |b| {
         b.iter_batched(
-            || SegBuffer::new(),
+            SegBuffer::new,
             |buffer| {
                 for x in 0..AMOUNT {
                     buffer.push(x);
@@ -25,7 +26,7 @@ fn seg_buffer_push_single_thread(c: &mut Criterion) {
 fn seg_queue_push_single_thread(c: &mut Criterion) {
     c.bench_function("queue_push_single_thread", |b| {
         b.iter_batched(
-            || SegQueue::new(),
+            SegQueue::new,
             |queue| {
                 for x in 0..AMOUNT {
                     queue.push(x);
@@ -53,7 +54,7 @@ fn array_queue_push_single_thread(c: &mut Criterion) {
 fn seg_buffer_push_concurrent(c: &mut Criterion) {
     c.bench_function("seg_buffer_push_concurrent", |b| {
         b.iter_batched(
-            || SegBuffer::new(),
+            SegBuffer::new,
             |buffer| {
                 scope(|s| {
                     for _ in 0..threads() {
@@ -74,7 +75,7 @@ fn seg_buffer_push_concurrent(c: &mut Criterion) {
 fn seg_queue_push_concurrent(c: &mut Criterion) {
     c.bench_function("seg_queue_push_concurrent", |b| {
         b.iter_batched(
-            || SegQueue::new(),
+            SegQueue::new,
             |queue| {
                 scope(|s| {
                     for _ in 0..threads() {
@@ -129,7 +130,7 @@ fn thread_creation(c: &mut Criterion) {
 fn seg_buffer_push_pop(c: &mut Criterion) {
     c.bench_function("seg_buffer_push_pop", |b| {
         b.iter_batched(
-            || SegBuffer::new(),
+            SegBuffer::new,
             |mut buffer| {
                 for x in 0..AMOUNT {
                     buffer.push(x);
@@ -144,7 +145,7 @@ fn seg_buffer_push_pop(c: &mut Criterion) {
 fn seg_queue_push_pop(c: &mut Criterion) {
     c.bench_function("seg_queue_push_pop", |b| {
         b.iter_batched(
-            || SegQueue::new(),
+            SegQueue::new,
             |queue| {
                 for x in 0..AMOUNT {
                     queue.push(x);
@@ -171,6 +172,68 @@ fn array_queue_push_pop(c: &mut Criterion) {
     });
 }
 
+fn seg_buffer_mpmc_push_pop_concurrent(c: &mut Criterion) {
+    c.bench_function("seg_buffer_mpmc_push_pop_concurrent", |b| {
+        b.iter_batched(
+            SegBufferMpmc::new,
+            |buffer| {
+                scope(|s| {
+                    for _ in 0..threads() {
+                        s.spawn(|_| {
+                            for x in 0..AMOUNT / threads() {
+                                buffer.push(x);
+                                buffer.pop();
+                            }
+                        });
+                    }
+                })
+                .unwrap();
+            },
+            BatchSize::SmallInput,
+        );
+    });
+}
+
+fn seg_queue_push_pop_concurrent(c: &mut Criterion) {
+    c.bench_function("seg_queue_push_pop_concurrent", |b| {
+        b.iter_batched(
+            SegQueue::new,
+            |queue| {
+                scope(|s| {
+                    for _ in 0..threads() {
+                        s.spawn(|_| {
+                            for x in 0..AMOUNT / threads() {
+                                queue.push(x);
+                                let _ = queue.pop();
+                            }
+                        });
+                    }
+                })
+                .unwrap();
+            },
+            BatchSize::SmallInput,
+        );
+    });
+}
+
+fn seg_buffer_allocate_segment(c: &mut Criterion) {
+    c.bench_function("seg_buffer_allocate_segment", |b| {
+        b.iter(|| SegBuffer::<u64>::with_capacity(LARGE_SEGMENT));
+    });
+}
+
+fn seg_buffer_fill_segments(c: &mut Criterion) {
+    c.bench_function("seg_buffer_fill_segments", |b| {
+        b.iter(|| {
+            let buffer = SegBuffer::new();
+            for x in 0..LARGE_SEGMENT {
+                buffer.push(x);
+            }
+            buffer
+        });
+    });
+}
+
 fn threads() -> usize {
     num_cpus::get()
 }
@@ -187,6 +250,8 @@ criterion_group!(
     seg_buffer_push_concurrent,
     seg_queue_push_concurrent,
     array_queue_push_concurrent,
+    seg_buffer_mpmc_push_pop_concurrent,
+    seg_queue_push_pop_concurrent,
 );
 criterion_group!(
     push_pop,
@@ -194,4 +259,9 @@ criterion_group!(
     seg_queue_push_pop,
     array_queue_push_pop,
 );
-criterion_main!(push_pop, single_thread, concurrent);
+criterion_group!(
+    allocation,
+    seg_buffer_allocate_segment,
+    seg_buffer_fill_segments,
+);
+criterion_main!(push_pop, single_thread, concurrent, allocation);
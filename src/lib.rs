@@ -1,10 +1,20 @@
-<<<<<<< HEAD
-extern crate crossbeam_epoch as epoch;
+#![cfg_attr(not(feature = "std"), no_std)]
 
-mod map;
-mod seg_buffer;
-=======
+extern crate alloc;
+
+pub mod map;
 pub mod seg_buffer;
->>>>>>> ed60be8c513a11e794d0df0b62818bee376cfada
 
-pub use seg_buffer::SegBuffer;
+pub use map::RashMap;
+#[cfg(feature = "metrics")]
+pub use map::MapStats;
+pub use seg_buffer::{Builder, Global, Producer, RetentionPolicy, SegBuffer, SegmentAllocator};
+#[cfg(feature = "std")]
+pub use seg_buffer::{
+    BlockingSegBuffer, BoundedSegBuffer, Receiver, SegBufferMpmc, SegmentPool, Sender,
+    ShardedSegBuffer, SwapBuffer,
+};
+#[cfg(feature = "async")]
+pub use seg_buffer::AsyncSegBuffer;
+#[cfg(feature = "metrics")]
+pub use seg_buffer::SegBufferStats;
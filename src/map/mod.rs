@@ -1,5 +1,7 @@
 use ahash::ABuildHasher;
 use crate::map::raw::RawMap;
+use core::hash::{BuildHasher, Hash};
+use crossbeam_epoch::{Guard, Shared};
 
 mod raw;
 
@@ -15,6 +17,56 @@ impl <K, V> RashMap<K, V, ABuildHasher> {
     }
 }
 
+impl<K, V> Default for RashMap<K, V, ABuildHasher> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl <K, V, H> RashMap<K, V, H> {
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
+
+    /// Returns whether the map holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the value stored for `key`, if any.
+    pub fn get<'guard>(&self, key: &K, guard: &'guard Guard) -> Option<Shared<'guard, V>>
+    where
+        K: Hash + Eq + Clone,
+        H: BuildHasher,
+    {
+        self.raw.get(key, guard)
+    }
+
+    /// Returns a snapshot of the events counted since the map was created.
+    ///
+    /// The map does not resize or remove entries yet, so there
+    /// are no resize events or tombstones to report.
+    ///
+    /// Requires the `metrics` feature.
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> MapStats {
+        self.raw.stats()
+    }
+}
 
+/// A snapshot of the events counted by a `RashMap`,
+/// returned by `RashMap::stats`.
+///
+/// Requires the `metrics` feature.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MapStats {
+    /// Number of entries in the map.
+    pub len: usize,
+    /// Number of lookups.
+    pub lookups: usize,
+    /// Total number of groups probed by lookups. Divided by
+    /// `lookups`, this gives the average probe length.
+    pub probed_groups: usize,
 }
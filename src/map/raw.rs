@@ -1,19 +1,24 @@
+#[cfg(feature = "metrics")]
+use crate::map::MapStats;
 use ahash::ABuildHasher;
+use alloc::vec::Vec;
 use arrayvec::ArrayVec;
-use epoch::{Atomic, Guard, Shared};
-use std::cell::UnsafeCell;
-use std::hash::{BuildHasher, Hash};
-use std::iter;
-use std::hash::Hasher;
-use std::mem::MaybeUninit;
-use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use crossbeam_epoch::{Atomic, Guard, Shared};
+use core::cell::UnsafeCell;
+use core::hash::{BuildHasher, Hash};
+use core::iter;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 
 const GROUP_SIZE: usize = 16;
 
 pub struct Group<K, V> {
     controls: [AtomicU64; GROUP_SIZE / 8],
+    // Not read until the map supports insertion.
+    #[allow(dead_code)]
     keys: [UnsafeCell<MaybeUninit<K>>; GROUP_SIZE],
     values: [UnsafeCell<MaybeUninit<Atomic<V>>>; GROUP_SIZE],
+    #[allow(dead_code)]
     inserting: AtomicBool,
 }
 
@@ -24,12 +29,14 @@ impl<K, V> Group<K, V> {
             keys: iter::repeat_with(|| UnsafeCell::new(MaybeUninit::uninit()))
                 .take(GROUP_SIZE)
                 .collect::<ArrayVec<[_; GROUP_SIZE]>>()
-                .inner()
+                .into_inner()
+                .ok()
                 .unwrap(),
-            values: iter::repeat_with(|| MaybeUninit::uninit())
+            values: iter::repeat_with(|| UnsafeCell::new(MaybeUninit::uninit()))
                 .take(GROUP_SIZE)
                 .collect::<ArrayVec<[_; GROUP_SIZE]>>()
-                .inner()
+                .into_inner()
+                .ok()
                 .unwrap(),
             inserting: AtomicBool::new(false),
         }
@@ -40,13 +47,15 @@ pub struct RawMap<K, V, H=ABuildHasher> {
     len: AtomicUsize,
     groups: Atomic<Vec<Group<K, V>>>,
     build_hasher: H,
+    /// Number of lookups, counted with the `metrics` feature.
+    #[cfg(feature = "metrics")]
+    lookups: AtomicUsize,
+    /// Total number of groups probed by lookups.
+    #[cfg(feature = "metrics")]
+    probed_groups: AtomicUsize,
 }
 
-impl<K, V> RawMap<K, V, ABuildHasher>
-where
-    H: BuildHasher,
-    K: Hash + PartialEq + Eq + Clone,
-{
+impl<K, V> RawMap<K, V, ABuildHasher> {
     pub fn new() -> Self {
         let groups = Atomic::new(iter::repeat_with(|| Group::new()).take(4).collect());
 
@@ -54,44 +63,71 @@ where
             len: AtomicUsize::new(0),
             groups,
             build_hasher: ABuildHasher::new(),
+            #[cfg(feature = "metrics")]
+            lookups: AtomicUsize::new(0),
+            #[cfg(feature = "metrics")]
+            probed_groups: AtomicUsize::new(0),
         }
     }
+}
+
+impl<K, V, H> RawMap<K, V, H> {
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> MapStats {
+        MapStats {
+            len: self.len.load(Ordering::Relaxed),
+            lookups: self.lookups.load(Ordering::Relaxed),
+            probed_groups: self.probed_groups.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl<K, V, H> RawMap<K, V, H>
+where
+    H: BuildHasher,
+    K: Hash + PartialEq + Eq + Clone,
+{
 
     pub fn get<'guard>(&self, key: &K, guard: &'guard Guard) -> Option<Shared<'guard, V>> {
         let groups = self.groups.load(Ordering::Relaxed, guard);
         let groups = unsafe { groups.as_ref() }.unwrap();
 
-        let mut hasher = self.build_hasher.build_hasher();
-        key.hash(&mut hasher);
-        let hash: u64 = hasher.finish();
+        let hash = self.build_hasher.hash_one(key);
 
         let group_index = hash as usize % groups.len();
         let group = &groups[group_index];
 
+        #[cfg(feature = "metrics")]
+        {
+            self.lookups.fetch_add(1, Ordering::Relaxed);
+            self.probed_groups.fetch_add(1, Ordering::Relaxed);
+        }
+
         let index_in_group = unsafe { Self::heuristic_probe_group(group, hash, true)? };
 
         let ptr = unsafe {
-            (&mut *group.values[index_in_group].get())
-                .load(Ordering::Relaxed)
-                .as_ref()
-                .unwrap()
+            (&*(*group.values[index_in_group].get()).as_ptr()).load(Ordering::Relaxed, guard)
         };
         Some(ptr)
     }
 
-    #[cfg(target_feature = "sse2")]
+    #[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
     unsafe fn heuristic_probe_group(
         group: &Group<K, V>,
         hash: u64,
         acquire: bool,
     ) -> Option<usize> {
-        use std::arch::x86_64::*;
+        use core::arch::x86_64::*;
 
         let control = {
             let a = group.controls[0].load(Ordering::Relaxed);
             let b = group.controls[1].load(Ordering::Relaxed);
 
-            _mm_setr_epi64(b, a)
+            _mm_set_epi64x(a as i64, b as i64)
         };
 
         let to_find = (0x01 << 7) | (hash >> (64 - 7));
@@ -113,4 +149,26 @@ where
             Some(leading_zeroes as usize)
         }
     }
+
+    /// Portable version of the SSE2 probe above.
+    #[cfg(not(all(target_arch = "x86_64", target_feature = "sse2")))]
+    unsafe fn heuristic_probe_group(
+        group: &Group<K, V>,
+        hash: u64,
+        acquire: bool,
+    ) -> Option<usize> {
+        let to_find = (0x01 << 7) | (hash >> (64 - 7));
+
+        // As in the SSE2 version, slot `i` is described by the
+        // `i % 8`th most significant byte of `controls[i / 8]`.
+        let index = (0..GROUP_SIZE).find(|&i| {
+            let control = group.controls[i / 8].load(Ordering::Relaxed);
+            (control >> (56 - 8 * (i % 8))) & 0xFF == to_find
+        })?;
+
+        if acquire {
+            unimplemented!("acquire");
+        }
+        Some(index)
+    }
 }
@@ -0,0 +1,87 @@
+use alloc::alloc::{alloc, dealloc, Layout};
+use alloc::sync::Arc;
+
+/// Allocates the memory backing the segments of a `SegBuffer`.
+///
+/// Each segment is a single allocation, so a buffer calls `allocate`
+/// once per segment and `deallocate` once the segment is freed.
+///
+/// # Safety
+/// `allocate` must return memory which fits `layout`, or null if
+/// allocation fails. The memory must remain valid until it is passed
+/// to `deallocate` with the same layout.
+///
+/// If `can_free` returns `true`, this allocator must also accept
+/// memory allocated by `other` in `deallocate`.
+pub unsafe trait SegmentAllocator {
+    /// Allocates memory for a segment.
+    fn allocate(&self, layout: Layout) -> *mut u8;
+
+    /// Frees memory returned by `allocate`.
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by `allocate` on this allocator
+    /// with the same `layout`, and must not be used afterwards.
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout);
+
+    /// Returns whether this allocator may free memory allocated by `other`.
+    ///
+    /// `SegBuffer::append` and `SegBuffer::split_off` move segments between
+    /// buffers only if their allocators may free each other's memory, and
+    /// move the values one by one otherwise. The default returns `false`.
+    fn can_free(&self, _other: &Self) -> bool {
+        false
+    }
+}
+
+/// The global allocator, used by default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Global;
+
+unsafe impl SegmentAllocator for Global {
+    fn allocate(&self, layout: Layout) -> *mut u8 {
+        unsafe { alloc(layout) }
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        dealloc(ptr, layout)
+    }
+
+    fn can_free(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+unsafe impl<A> SegmentAllocator for &A
+where
+    A: SegmentAllocator + ?Sized,
+{
+    fn allocate(&self, layout: Layout) -> *mut u8 {
+        (**self).allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        (**self).deallocate(ptr, layout)
+    }
+
+    fn can_free(&self, other: &Self) -> bool {
+        (**self).can_free(&**other)
+    }
+}
+
+unsafe impl<A> SegmentAllocator for Arc<A>
+where
+    A: SegmentAllocator + ?Sized,
+{
+    fn allocate(&self, layout: Layout) -> *mut u8 {
+        (**self).allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        (**self).deallocate(ptr, layout)
+    }
+
+    fn can_free(&self, other: &Self) -> bool {
+        (**self).can_free(&**other)
+    }
+}
@@ -0,0 +1,64 @@
+use crate::seg_buffer::mpmc::SegBufferMpmc;
+use crate::seg_buffer::wake::Waiters;
+use std::time::{Duration, Instant};
+
+/// A variant of `SegBufferMpmc` whose consumers can block until
+/// a value is available, instead of spinning on `pop`.
+///
+/// Consumers spin briefly before parking on a condition variable.
+/// Producers only touch the condition variable if a consumer is
+/// actually parked. To find out, each push loads an atomic counter
+/// of parked consumers, on top of what `SegBufferMpmc::push` costs.
+pub struct BlockingSegBuffer<T> {
+    buffer: SegBufferMpmc<T>,
+    /// Consumers waiting for a value.
+    consumers: Waiters,
+}
+
+impl<T> Default for BlockingSegBuffer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> BlockingSegBuffer<T> {
+    /// Creates a new, empty `BlockingSegBuffer<T>`.
+    pub fn new() -> Self {
+        Self {
+            buffer: SegBufferMpmc::new(),
+            consumers: Waiters::new(),
+        }
+    }
+
+    /// Pushes an element to the buffer, waking a parked consumer if there is one.
+    pub fn push(&self, value: T) {
+        self.buffer.push(value);
+        self.consumers.notify();
+    }
+
+    /// Pops an element from the front of the buffer.
+    ///
+    /// Returns `None` without blocking if the buffer is empty.
+    pub fn pop(&self) -> Option<T> {
+        self.buffer.pop()
+    }
+
+    /// Pops an element from the front of the buffer, blocking
+    /// the current thread until one is available.
+    pub fn pop_blocking(&self) -> T {
+        loop {
+            if let Some(value) = self.consumers.wait_until(None, || self.pop()) {
+                return value;
+            }
+        }
+    }
+
+    /// Pops an element from the front of the buffer, blocking the current
+    /// thread for at most `timeout` until one is available.
+    ///
+    /// Returns `None` if the buffer is still empty once `timeout` has elapsed.
+    pub fn pop_timeout(&self, timeout: Duration) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        self.consumers.wait_until(Some(deadline), || self.pop())
+    }
+}
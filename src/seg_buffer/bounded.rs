@@ -0,0 +1,126 @@
+use crate::seg_buffer::mpmc::SegBufferMpmc;
+use crate::seg_buffer::raw::Geometry;
+use crate::seg_buffer::wake::Waiters;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A variant of `SegBufferMpmc` which holds at most a fixed number
+/// of values, applying backpressure to producers once it is full.
+///
+/// Values are stored in the same list of segments as in `SegBuffer`.
+/// A separate counter of the values in the buffer bounds its length:
+/// `try_push` fails once the bound is reached, and `push_blocking`
+/// parks until a consumer pops a value. Consumers only touch the
+/// condition variable if a producer is actually parked, but each pop
+/// loads a counter of parked producers to find out.
+pub struct BoundedSegBuffer<T> {
+    buffer: SegBufferMpmc<T>,
+    /// Number of values pushed but not yet popped.
+    len: AtomicUsize,
+    max_len: usize,
+    /// Producers waiting for room.
+    producers: Waiters,
+}
+
+impl<T> BoundedSegBuffer<T> {
+    /// Creates a new, empty `BoundedSegBuffer<T>` which holds at most `max_len` values.
+    ///
+    /// # Panics
+    /// Panics if `max_len` is zero.
+    pub fn new(max_len: usize) -> Self {
+        Self::with_geometry(max_len, Geometry::default())
+    }
+
+    /// Creates a new, empty `BoundedSegBuffer<T>` which holds at most as
+    /// many values as fit in `max_segments` segments of `segment_capacity`
+    /// values each.
+    ///
+    /// Every segment has the same capacity, so the buffer's values never
+    /// span more than `max_segments + 1` segments.
+    ///
+    /// # Panics
+    /// Panics if `max_segments` or `segment_capacity` is zero.
+    pub fn with_max_segments(max_segments: usize, segment_capacity: usize) -> Self {
+        assert!(segment_capacity > 0, "segment capacity must be nonzero");
+        let geometry = Geometry {
+            starting_capacity: segment_capacity,
+            growth_factor: 1,
+            max_capacity: segment_capacity,
+        };
+        Self::with_geometry(max_segments.saturating_mul(segment_capacity), geometry)
+    }
+
+    fn with_geometry(max_len: usize, geometry: Geometry) -> Self {
+        assert!(max_len > 0, "maximum length must be nonzero");
+        Self {
+            buffer: SegBufferMpmc::with_geometry(geometry),
+            len: AtomicUsize::new(0),
+            max_len,
+            producers: Waiters::new(),
+        }
+    }
+
+    /// Returns the maximum number of values the buffer can hold.
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Returns the number of values in the buffer.
+    ///
+    /// Other threads may push or pop values concurrently,
+    /// so this is only a snapshot.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the buffer holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Pushes an element to the buffer.
+    ///
+    /// Returns the element back if the buffer is full.
+    pub fn try_push(&self, value: T) -> Result<(), T> {
+        let mut len = self.len.load(Ordering::Relaxed);
+        loop {
+            if len >= self.max_len {
+                return Err(value);
+            }
+            match self
+                .len
+                .compare_exchange_weak(len, len + 1, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(current) => len = current,
+            }
+        }
+
+        self.buffer.push(value);
+        Ok(())
+    }
+
+    /// Pushes an element to the buffer, blocking the current
+    /// thread until there is room for it.
+    pub fn push_blocking(&self, value: T) {
+        let mut value = Some(value);
+        self.producers
+            .wait_until(None, || match self.try_push(value.take().unwrap()) {
+                Ok(()) => Some(()),
+                Err(v) => {
+                    value = Some(v);
+                    None
+                }
+            });
+    }
+
+    /// Pops an element from the front of the buffer,
+    /// waking a parked producer if there is one.
+    ///
+    /// Returns `None` if the buffer is empty.
+    pub fn pop(&self) -> Option<T> {
+        let value = self.buffer.pop()?;
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        self.producers.notify();
+        Some(value)
+    }
+}
@@ -0,0 +1,141 @@
+use crate::seg_buffer::raw::{self, Geometry, RawBuffer};
+use crate::seg_buffer::{Global, RetentionPolicy, SegmentAllocator};
+use crate::SegBuffer;
+use core::marker::PhantomData;
+
+/// A size given either in elements or in bytes.
+#[derive(Debug, Clone, Copy)]
+enum Size {
+    Elements(usize),
+    Bytes(usize),
+}
+
+impl Size {
+    fn capacity<T>(self) -> usize {
+        match self {
+            Size::Elements(capacity) => capacity,
+            Size::Bytes(bytes) => raw::capacity_for_bytes::<T>(bytes),
+        }
+    }
+}
+
+/// Configures the segments of a `SegBuffer`.
+///
+/// Returned by `SegBuffer::builder`. By default, the first segment holds 64 values,
+/// and each following segment is twice as large as the previous one, up to
+/// 262,144 values.
+///
+/// Sizes may be given in elements or in bytes. Sizes in bytes count the storage
+/// used by each slot, and are rounded down to a whole number of elements.
+///
+/// Segments are allocated with the global allocator unless another
+/// `SegmentAllocator` is given.
+pub struct Builder<T, A = Global> {
+    starting: Size,
+    max: Size,
+    growth_factor: usize,
+    retention: RetentionPolicy,
+    allocator: A,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Default for Builder<T> {
+    fn default() -> Self {
+        let geometry = Geometry::default();
+        Self {
+            starting: Size::Elements(geometry.starting_capacity),
+            max: Size::Elements(geometry.max_capacity),
+            growth_factor: geometry.growth_factor,
+            retention: RetentionPolicy::default(),
+            allocator: Global,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Builder<T> {
+    /// Creates a builder with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T, A: SegmentAllocator> Builder<T, A> {
+    /// Sets the number of values the first segment can hold.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    pub fn starting_capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "segment capacity must be nonzero");
+        self.starting = Size::Elements(capacity);
+        self
+    }
+
+    /// Sets the size of the first segment in bytes.
+    pub fn starting_bytes(mut self, bytes: usize) -> Self {
+        self.starting = Size::Bytes(bytes);
+        self
+    }
+
+    /// Sets the number of values the largest segments can hold.
+    ///
+    /// Segments larger than this are only allocated to hold a run of values
+    /// pushed with `SegBuffer::push_many`, or if the starting capacity is larger.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    pub fn max_capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "segment capacity must be nonzero");
+        self.max = Size::Elements(capacity);
+        self
+    }
+
+    /// Sets the size of the largest segments in bytes.
+    pub fn max_bytes(mut self, bytes: usize) -> Self {
+        self.max = Size::Bytes(bytes);
+        self
+    }
+
+    /// Sets the factor by which the capacity of each new segment grows.
+    ///
+    /// A factor of 1 allocates all segments with the starting capacity.
+    ///
+    /// # Panics
+    /// Panics if `factor` is zero.
+    pub fn growth_factor(mut self, factor: usize) -> Self {
+        assert!(factor > 0, "growth factor must be nonzero");
+        self.growth_factor = factor;
+        self
+    }
+
+    /// Sets the policy for keeping spare segments.
+    pub fn retention_policy(mut self, policy: RetentionPolicy) -> Self {
+        self.retention = policy;
+        self
+    }
+
+    /// Sets the allocator for segments.
+    pub fn allocator<B: SegmentAllocator>(self, allocator: B) -> Builder<T, B> {
+        Builder {
+            starting: self.starting,
+            max: self.max,
+            growth_factor: self.growth_factor,
+            retention: self.retention,
+            allocator,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Creates a `SegBuffer`, allocating its first segment.
+    pub fn build(self) -> SegBuffer<T, A> {
+        let geometry = Geometry {
+            starting_capacity: self.starting.capacity::<T>(),
+            growth_factor: self.growth_factor,
+            max_capacity: self.max.capacity::<T>(),
+        };
+        let mut raw = RawBuffer::new_in(geometry, self.allocator);
+        raw.set_retention(self.retention);
+
+        SegBuffer { raw }
+    }
+}
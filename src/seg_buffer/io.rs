@@ -0,0 +1,52 @@
+use crate::seg_buffer::allocator::SegmentAllocator;
+use crate::SegBuffer;
+use std::io::{self, IoSlice, Read, Write};
+
+impl<A: SegmentAllocator> SegBuffer<u8, A> {
+    /// Returns the contents of the buffer as one `IoSlice` per segment,
+    /// so that a single `write_vectored` call can write all of them.
+    pub fn io_slices(&mut self) -> Vec<IoSlice<'_>> {
+        self.iter_slices().map(IoSlice::new).collect()
+    }
+}
+
+/// Appends bytes to the buffer. Each call to `write` claims one
+/// contiguous run of bytes, so bytes written by other threads
+/// never end up in the middle of it.
+impl<A: SegmentAllocator> Write for &SegBuffer<u8, A> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<A: SegmentAllocator> Write for SegBuffer<u8, A> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        (&*self).write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Removes bytes from the front of the buffer, in the order
+/// they were written.
+impl<A: SegmentAllocator> Read for SegBuffer<u8, A> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(self.pop_slice(buf))
+    }
+}
@@ -0,0 +1,90 @@
+//! Event counters for tuning the segment geometry.
+//!
+//! With the `metrics` feature disabled, `Metrics` is a zero-sized
+//! type whose methods do nothing, so the counters cost nothing.
+
+#[cfg(feature = "metrics")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A snapshot of the events counted by a `SegBuffer`,
+/// returned by `SegBuffer::stats`.
+///
+/// Requires the `metrics` feature.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SegBufferStats {
+    /// Number of segments allocated.
+    pub segments_allocated: usize,
+    /// Number of fully read segments kept for reuse by `pop` and `pop_back`.
+    pub segments_recycled: usize,
+    /// Number of times a push found the head segment full
+    /// and retried in the following segment.
+    pub head_retries: usize,
+    /// Number of segments allocated for growth which were linked
+    /// further down the list, because another segment was linked first.
+    pub wasted_segments: usize,
+}
+
+#[cfg(feature = "metrics")]
+#[derive(Default)]
+pub struct Metrics {
+    segments_allocated: AtomicUsize,
+    segments_recycled: AtomicUsize,
+    head_retries: AtomicUsize,
+    wasted_segments: AtomicUsize,
+}
+
+#[cfg(feature = "metrics")]
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn segment_allocated(&self) {
+        self.segments_allocated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn segment_recycled(&self) {
+        self.segments_recycled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn head_retry(&self) {
+        self.head_retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn segment_wasted(&self) {
+        self.wasted_segments.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> SegBufferStats {
+        SegBufferStats {
+            segments_allocated: self.segments_allocated.load(Ordering::Relaxed),
+            segments_recycled: self.segments_recycled.load(Ordering::Relaxed),
+            head_retries: self.head_retries.load(Ordering::Relaxed),
+            wasted_segments: self.wasted_segments.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+pub struct Metrics;
+
+#[cfg(not(feature = "metrics"))]
+impl Metrics {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Metrics
+    }
+
+    #[inline(always)]
+    pub fn segment_allocated(&self) {}
+
+    #[inline(always)]
+    pub fn segment_recycled(&self) {}
+
+    #[inline(always)]
+    pub fn head_retry(&self) {}
+
+    #[inline(always)]
+    pub fn segment_wasted(&self) {}
+}
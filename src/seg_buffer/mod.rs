@@ -1,17 +1,77 @@
-use crate::seg_buffer::raw::RawIter;
+use crate::seg_buffer::raw::{RawIter, RawSnapshotIter};
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt::{self, Debug};
+use core::hash::{Hash, Hasher};
+use core::iter::{Flatten, FromIterator};
 use raw::RawBuffer;
-use std::iter::{Flatten, FromIterator};
 
+mod allocator;
+#[cfg(feature = "std")]
+mod blocking;
+#[cfg(feature = "std")]
+mod bounded;
+mod builder;
+#[cfg(feature = "std")]
+mod io;
+mod metrics;
+#[cfg(feature = "std")]
+mod mpmc;
+#[cfg(feature = "std")]
+mod pool;
+mod producer;
 mod raw;
 #[cfg(feature = "rayon")]
 mod rayon;
+#[cfg(feature = "std")]
+mod sharded;
+#[cfg(feature = "std")]
+mod split;
+#[cfg(feature = "async")]
+mod stream;
+#[cfg(feature = "std")]
+mod swap;
+mod sync;
+#[cfg(feature = "std")]
+mod wake;
+
+pub use self::allocator::{Global, SegmentAllocator};
+#[cfg(feature = "std")]
+pub use self::blocking::BlockingSegBuffer;
+#[cfg(feature = "std")]
+pub use self::bounded::BoundedSegBuffer;
+pub use self::builder::Builder;
+#[cfg(feature = "metrics")]
+pub use self::metrics::SegBufferStats;
+#[cfg(feature = "std")]
+pub use self::mpmc::SegBufferMpmc;
+#[cfg(feature = "std")]
+pub use self::pool::SegmentPool;
+pub use self::producer::Producer;
+#[cfg(feature = "rayon")]
+pub use self::sharded::ShardedParIter;
+#[cfg(feature = "std")]
+pub use self::sharded::{ShardedIter, ShardedSegBuffer};
+#[cfg(feature = "std")]
+pub use self::split::{Drain, Receiver, Sender, TryIter};
+#[cfg(feature = "async")]
+pub use self::stream::AsyncSegBuffer;
+#[cfg(feature = "std")]
+pub use self::swap::SwapBuffer;
 
 #[cfg(feature = "rayon")]
 pub use self::rayon::*;
 #[cfg(feature = "rayon")]
-use ::rayon::iter::ParallelIterator;
+use ::rayon::iter::{IntoParallelIterator, ParallelIterator};
 
-/// An unbounded, wait-free buffer implemented using a linked list of segments.
+/// An unbounded, lock-free buffer implemented using a linked list of segments.
+///
+/// Pushing a value takes a single atomic increment unless the current segment is full.
+/// Once a segment is half full, one producer allocates the segment which follows it,
+/// so that it is usually linked before the segment fills up. Producers which fill the
+/// rest of the segment before that allocation completes wait briefly, then allocate
+/// a segment themselves rather than block on that producer; only one of these segments
+/// follows the full one and the others are kept as spares.
 ///
 /// Akin to `crossbeam::SegQueue`, this data structure acts as a queue of values.
 /// However `SegBuffer` is more specialized in that it only supports _either_ reading
@@ -23,9 +83,58 @@ use ::rayon::iter::ParallelIterator;
 /// lacks. These include:
 /// * Iterating over elements in the buffer, using either normal iterators or Rayon parallel iterators.
 /// * Efficient O(1) pushing of a vector of elements at once.
+/// * Pushing a contiguous run of elements with a single atomic operation.
 /// * Direct slice access to the inner values.p
-pub struct SegBuffer<T> {
-    raw: RawBuffer<T>,
+/// * Reading a snapshot of the published values while other threads are still writing.
+/// * Per-thread `Producer` handles which reserve slots in blocks.
+/// * Custom segment allocators, such as a `SegmentPool` shared between buffers.
+/// * Appending a buffer to another in constant time.
+///
+/// See `SegBufferMpmc` for a variant with concurrent consumers and `SwapBuffer`
+/// for taking the contents of a buffer while writers keep pushing. `split`
+/// divides a buffer into a `Sender` and a `Receiver` which pops while
+/// other threads push.
+///
+/// `SegBuffer` only needs `alloc`, so it is available in `no_std` builds
+/// with the default `std` feature disabled. `SegBufferMpmc`, `SwapBuffer`,
+/// `SegmentPool`, `split` and the Rayon integration require `std`.
+///
+/// # Panic safety
+/// A panic in user code never leaves a `SegBuffer` in an invalid state,
+/// and no value is ever dropped twice:
+/// * `push`, `pop` and `pop_back` run no user code besides moving values
+///   and calling the segment allocator. If allocating a segment panics in
+///   `push` or `push_many`, nothing is pushed, the slots claimed for the
+///   call are skipped, and a later push allocates the segment instead. If
+///   freeing a spare segment panics in `pop` or `pop_back`, the value is
+///   not lost; the next call returns it.
+/// * If a closure given to `push_many`, `retain`, the iterators or the
+///   sorting methods panics, the values in the buffer stay valid. Slots
+///   which `push_many` had not written yet are skipped.
+/// * If dropping a value panics in `clear`, or while the buffer itself is
+///   dropped, the remaining values are still dropped and the segments
+///   are freed before the panic continues.
+/// * If the source of `collect` panics, the values collected so far are dropped.
+///
+/// `SegBuffer<T>` is `UnwindSafe` and `RefUnwindSafe` if `T` is.
+pub struct SegBuffer<T, A: SegmentAllocator = Global> {
+    raw: RawBuffer<T, A>,
+}
+
+/// Limits how much memory a `SegBuffer` keeps in spare segments.
+///
+/// Segments are recycled once all of their values have been popped,
+/// and spare segments are reused before new ones are allocated.
+/// Spare segments beyond these limits are freed instead.
+///
+/// The default policy keeps every spare segment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Maximum number of spare segments to keep, or `None` for no limit.
+    pub max_segments: Option<usize>,
+    /// Maximum number of bytes of storage to keep in spare segments,
+    /// or `None` for no limit.
+    pub max_bytes: Option<usize>,
 }
 
 impl<T> Default for SegBuffer<T> {
@@ -42,43 +151,333 @@ impl<T> SegBuffer<T> {
         }
     }
 
+    /// Creates a new, empty `SegBuffer<T>` whose first segment
+    /// can hold `capacity` values.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::builder().starting_capacity(capacity).build()
+    }
+
+    /// Returns a builder for configuring the segments of a new `SegBuffer<T>`.
+    pub fn builder() -> Builder<T> {
+        Builder::new()
+    }
+}
+
+impl<T, A: SegmentAllocator> SegBuffer<T, A> {
+    /// Creates a new, empty `SegBuffer<T>` whose segments are
+    /// allocated by `allocator`.
+    pub fn new_in(allocator: A) -> Self {
+        Builder::new().allocator(allocator).build()
+    }
+
     /// Pushes an element to the buffer.
     pub fn push(&self, value: T) {
         unsafe { self.raw.push(value) }
     }
 
-    /// Pops an element from the back of the buffer.
+    /// Pushes `len` elements as one contiguous run.
+    ///
+    /// `f` is called with the index of each element within the run.
+    /// All slots are claimed with a single atomic operation, so values
+    /// pushed by other threads never end up in the middle of the run.
+    pub fn push_many<F>(&self, len: usize, mut f: F)
+    where
+        F: FnMut(usize) -> T,
+    {
+        if len == 0 {
+            return;
+        }
+
+        let mut reservation = unsafe { self.raw.reserve_exact(len) };
+
+        for i in 0..len {
+            unsafe { reservation.write(f(i)) }
+        }
+    }
+
+    /// Copies all values in `values` into the buffer as one contiguous run.
+    ///
+    /// Like `push_many`, the slots are claimed with a single atomic operation.
+    pub fn extend_from_slice(&self, values: &[T])
+    where
+        T: Copy,
+    {
+        if values.is_empty() {
+            return;
+        }
+
+        let mut reservation = unsafe { self.raw.reserve_exact(values.len()) };
+        unsafe { reservation.write_slice(values) }
+    }
+
+    /// Returns a handle which pushes values in blocks, reducing
+    /// contention with other threads.
+    ///
+    /// Use one producer per thread which pushes many values.
+    pub fn producer(&self) -> Producer<'_, T, A> {
+        Producer::new(self)
+    }
+
+    /// Pops the oldest element from the front of the buffer.
+    ///
+    /// Equivalent to `pop_front`.
     pub fn pop(&mut self) -> Option<T> {
+        self.pop_front()
+    }
+
+    /// Pops an element from the front of the buffer, in the order the
+    /// elements were pushed (FIFO).
+    ///
+    /// Fully read segments are recycled as spare segments.
+    pub fn pop_front(&mut self) -> Option<T> {
         unsafe { self.raw.pop() }
     }
 
+    /// Pops elements from the front of the buffer into `dst`, in the
+    /// order they were pushed. Returns the number of elements popped,
+    /// which is less than `dst.len()` only if the buffer ran empty.
+    pub fn pop_slice(&mut self, dst: &mut [T]) -> usize
+    where
+        T: Copy,
+    {
+        unsafe { self.raw.pop_into(dst) }
+    }
+
+    /// Pops an element from the back of the buffer, most recently
+    /// pushed first (LIFO).
+    ///
+    /// Once the last segment is empty, it is kept as a spare segment.
+    pub fn pop_back(&mut self) -> Option<T> {
+        unsafe { self.raw.pop_back() }
+    }
+
+    /// Returns a snapshot of the events counted since the buffer was created.
+    ///
+    /// Requires the `metrics` feature.
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> SegBufferStats {
+        self.raw.metrics().stats()
+    }
+
+    /// Returns the policy for keeping spare segments.
+    pub fn retention_policy(&self) -> RetentionPolicy {
+        self.raw.retention()
+    }
+
+    /// Sets the policy for keeping spare segments, freeing any
+    /// spare segments beyond its limits.
+    pub fn set_retention_policy(&mut self, policy: RetentionPolicy) {
+        self.raw.set_retention(policy)
+    }
+
+    /// Frees all spare segments, so that the memory used by the buffer
+    /// follows the number of values it currently holds.
+    pub fn shrink_to_fit(&mut self) {
+        self.raw.shrink_to_fit()
+    }
+
+    /// Removes and drops all values in the buffer.
+    ///
+    /// The segments are kept as spare segments, subject to the retention policy.
+    pub fn clear(&mut self) {
+        self.raw.clear()
+    }
+
+    /// Moves all values of `other` to the end of this buffer, leaving `other` empty.
+    ///
+    /// This takes constant time: the segments of `other` are linked after
+    /// the last segment of this buffer rather than copied. If the allocators
+    /// of the two buffers cannot free each other's memory, as reported by
+    /// `SegmentAllocator::can_free`, the values are moved one by one instead.
+    pub fn append(&mut self, other: &mut Self) {
+        self.raw.append(&mut other.raw)
+    }
+
+    /// Splits the buffer in two at the given index.
+    ///
+    /// Returns a new buffer containing the values from index `at` onwards,
+    /// leaving this buffer with the values before it. Whole segments are moved
+    /// to the new buffer; only the values after `at` in the segment containing
+    /// it are copied. If a clone of the allocator cannot free memory allocated
+    /// by this one, as reported by `SegmentAllocator::can_free`, the values
+    /// from `at` onwards are moved one by one instead.
+    ///
+    /// # Panics
+    /// Panics if `at` is greater than the number of values in the buffer.
+    pub fn split_off(&mut self, at: usize) -> Self
+    where
+        A: Clone,
+    {
+        Self {
+            raw: self.raw.split_off(at),
+        }
+    }
+
+    /// Converts the buffer into a `Vec` holding its values in order.
+    ///
+    /// The values are always copied into a new allocation: each segment
+    /// stores its header and slot states in the same allocation as its
+    /// values, so no segment can be handed over to a `Vec`.
+    pub fn into_vec(mut self) -> Vec<T> {
+        let mut vec = Vec::new();
+        self.raw.drain_into(&mut vec);
+        vec
+    }
+
+    /// Copies all values in the buffer into `dst`, in order.
+    ///
+    /// # Panics
+    /// Panics if the length of `dst` differs from the number
+    /// of values in the buffer.
+    pub fn copy_to_slice(&mut self, dst: &mut [T])
+    where
+        T: Copy,
+    {
+        let len: usize = self.iter_slices().map(|slice| slice.len()).sum();
+        assert_eq!(len, dst.len(), "destination slice length does not match");
+
+        let mut offset = 0;
+        for slice in self.iter_slices() {
+            dst[offset..offset + slice.len()].copy_from_slice(slice);
+            offset += slice.len();
+        }
+    }
+
+    /// Copies all values in the buffer into `dst` in parallel, in order.
+    ///
+    /// Each segment is copied to its offset in `dst` concurrently.
+    ///
+    /// # Panics
+    /// Panics if the length of `dst` differs from the number
+    /// of values in the buffer.
+    #[cfg(feature = "rayon")]
+    pub fn par_copy_to_slice(&mut self, dst: &mut [T])
+    where
+        T: Copy + Send + Sync,
+    {
+        let slices: Vec<&[T]> = self.iter_slices().collect();
+        let len: usize = slices.iter().map(|slice| slice.len()).sum();
+        assert_eq!(len, dst.len(), "destination slice length does not match");
+
+        let mut chunks = Vec::with_capacity(slices.len());
+        let mut rest = dst;
+        for slice in slices {
+            let (chunk, tail) = rest.split_at_mut(slice.len());
+            chunks.push((slice, chunk));
+            rest = tail;
+        }
+
+        chunks
+            .into_par_iter()
+            .for_each(|(slice, chunk)| chunk.copy_from_slice(slice));
+    }
+
+    /// Retains only the values for which `f` returns `true`,
+    /// preserving their order.
+    ///
+    /// Each segment is compacted in place, so segments
+    /// may be left partially filled.
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.raw.retain(f)
+    }
+
+    /// Removes all but the first of consecutive values
+    /// which map to the same key.
+    pub fn dedup_by_key<K, F>(&mut self, mut key: F)
+    where
+        K: PartialEq,
+        F: FnMut(&mut T) -> K,
+    {
+        self.raw.dedup_by(|a, b| key(a) == key(b))
+    }
+
+    /// Sorts the buffer, but may not preserve the order of equal values.
+    pub fn sort_unstable(&mut self)
+    where
+        T: Ord,
+    {
+        self.sort_unstable_by(T::cmp)
+    }
+
+    /// Sorts the buffer with a comparator function, but may not
+    /// preserve the order of equal values.
+    ///
+    /// Each segment is sorted in place, then the segments are merged into
+    /// newly allocated segments. Every old segment is freed as soon as all
+    /// of its values have been moved.
+    pub fn sort_unstable_by<F>(&mut self, mut compare: F)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        for slice in self.iter_slices_mut() {
+            slice.sort_unstable_by(&mut compare);
+        }
+
+        self.raw.merge_sorted(compare)
+    }
+
+    /// Sorts the buffer in parallel, but may not preserve the order of equal values.
+    ///
+    /// Segments are sorted in parallel and then merged as in `sort_unstable_by`.
+    #[cfg(feature = "rayon")]
+    pub fn par_sort_unstable(&mut self)
+    where
+        T: Ord + Send,
+    {
+        self.par_iter_slices_mut()
+            .for_each(|slice| slice.sort_unstable());
+
+        self.raw.merge_sorted(T::cmp)
+    }
+
     /// Returns an iterator over slices in the buffer in order.
-    pub fn iter_slices(&mut self) -> SliceIter<T> {
+    pub fn iter_slices(&mut self) -> SliceIter<'_, T> {
         SliceIter {
             raw: self.raw.iter(),
         }
     }
 
     /// Returns an iterator over mutable slices in the buffer in order.
-    pub fn iter_slices_mut(&mut self) -> SliceIterMut<T> {
+    pub fn iter_slices_mut(&mut self) -> SliceIterMut<'_, T> {
         SliceIterMut {
             raw: self.raw.iter(),
         }
     }
 
     /// Returns an iterator over references to values in the buffer in order.
-    pub fn iter(&mut self) -> Iter<T> {
+    pub fn iter(&mut self) -> Iter<'_, T> {
         self.iter_slices().flatten()
     }
 
     /// Returns an iterator over mutable references to values in the buffer in order.
-    pub fn iter_mut(&mut self) -> IterMut<T> {
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
         self.iter_slices_mut().flatten()
     }
 
+    /// Returns an iterator over references to values in the buffer in order,
+    /// which may be used while other threads are pushing.
+    ///
+    /// Values are yielded only once their `push` has completed. The iterator
+    /// stops at the first value which is still being written, so it always
+    /// observes a prefix of the buffer.
+    pub fn snapshot_iter(&self) -> SnapshotIter<'_, T>
+    where
+        T: Sync,
+    {
+        SnapshotIter {
+            raw: self.raw.snapshot(),
+        }
+    }
+
     /// Returns a parallel iterator over slices in the buffer in order.
     #[cfg(feature = "rayon")]
-    pub fn par_iter_slices(&mut self) -> ParSliceIter<T>
+    pub fn par_iter_slices(&mut self) -> ParSliceIter<'_, T>
     where
         T: Send,
     {
@@ -89,7 +488,7 @@ impl<T> SegBuffer<T> {
 
     /// Returns a parallel iterator over mutable slices in the buffer in order.
     #[cfg(feature = "rayon")]
-    pub fn par_iter_slices_mut(&mut self) -> ParSliceIterMut<T>
+    pub fn par_iter_slices_mut(&mut self) -> ParSliceIterMut<'_, T>
     where
         T: Send,
     {
@@ -98,7 +497,7 @@ impl<T> SegBuffer<T> {
 
     /// Returns a parallel iterator over references to values in the buffer in order.
     #[cfg(feature = "rayon")]
-    pub fn par_iter(&mut self) -> ParIter<T>
+    pub fn par_iter(&mut self) -> ParIter<'_, T>
     where
         T: Send + Sync,
     {
@@ -107,7 +506,7 @@ impl<T> SegBuffer<T> {
 
     /// Returns a parallel iterator over mutable references to values in the buffer in order.
     #[cfg(feature = "rayon")]
-    pub fn par_iter_mut(&mut self) -> ParIterMut<T>
+    pub fn par_iter_mut(&mut self) -> ParIterMut<'_, T>
     where
         T: Send,
     {
@@ -125,6 +524,66 @@ impl<T> FromIterator<T> for SegBuffer<T> {
     }
 }
 
+impl<T, A: SegmentAllocator> Extend<T> for SegBuffer<T, A> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|x| self.push(x));
+    }
+}
+
+impl<'a, T: Copy + 'a, A: SegmentAllocator> Extend<&'a T> for SegBuffer<T, A> {
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|&x| self.push(x));
+    }
+}
+
+// The impls below read the buffer through `&self`, like `snapshot_iter`,
+// so they need `T: Sync` in addition to the usual bounds.
+
+/// Clones the buffer, keeping the capacity of each segment
+/// and the values it holds.
+impl<T, A> Clone for SegBuffer<T, A>
+where
+    T: Clone + Sync,
+    A: SegmentAllocator + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            raw: self.raw.clone_segments(),
+        }
+    }
+}
+
+impl<T: Debug + Sync, A: SegmentAllocator> Debug for SegBuffer<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.snapshot_iter()).finish()
+    }
+}
+
+/// Compares the values in the buffers, regardless of how they are split into segments.
+impl<T, A, B> PartialEq<SegBuffer<T, B>> for SegBuffer<T, A>
+where
+    T: PartialEq + Sync,
+    A: SegmentAllocator,
+    B: SegmentAllocator,
+{
+    fn eq(&self, other: &SegBuffer<T, B>) -> bool {
+        self.snapshot_iter().eq(other.snapshot_iter())
+    }
+}
+
+impl<T: Eq + Sync, A: SegmentAllocator> Eq for SegBuffer<T, A> {}
+
+impl<T: Hash + Sync, A: SegmentAllocator> Hash for SegBuffer<T, A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut len = 0;
+        for value in self.snapshot_iter() {
+            value.hash(state);
+            len += 1;
+        }
+        state.write_usize(len);
+    }
+}
+
 pub struct SliceIter<'a, T> {
     raw: RawIter<'a, T>,
 }
@@ -140,6 +599,15 @@ impl<'a, T> Iterator for SliceIter<'a, T> {
     }
 }
 
+impl<'a, T> DoubleEndedIterator for SliceIter<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self.raw.next_back() {
+            Some(slice) => Some(slice),
+            None => None,
+        }
+    }
+}
+
 pub struct SliceIterMut<'a, T> {
     raw: RawIter<'a, T>,
 }
@@ -152,5 +620,25 @@ impl<'a, T> Iterator for SliceIterMut<'a, T> {
     }
 }
 
+impl<'a, T> DoubleEndedIterator for SliceIterMut<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.raw.next_back()
+    }
+}
+
+/// An iterator over published values in a `SegBuffer`,
+/// returned by `SegBuffer::snapshot_iter`.
+pub struct SnapshotIter<'a, T> {
+    raw: RawSnapshotIter<'a, T>,
+}
+
+impl<'a, T> Iterator for SnapshotIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.raw.next()
+    }
+}
+
 pub type Iter<'a, T> = Flatten<SliceIter<'a, T>>;
 pub type IterMut<'a, T> = Flatten<SliceIterMut<'a, T>>;
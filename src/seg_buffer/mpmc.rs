@@ -0,0 +1,56 @@
+use crate::seg_buffer::raw::{Geometry, RawBuffer};
+use crate::seg_buffer::sync::epoch;
+use crate::seg_buffer::Global;
+
+/// A variant of `SegBuffer` which supports multiple concurrent consumers.
+///
+/// Pushing works exactly like in `SegBuffer`, and values are stored in the
+/// same doubling list of segments. However, `pop` takes `&self` and may run
+/// concurrently with both `push` and other calls to `pop`, like in `SegQueue`.
+///
+/// Readers claim slots by incrementing a per-segment counter, mirroring how
+/// writers claim them. Once every slot in a segment has been read, the segment
+/// is released using epoch-based reclamation, so that it is freed only after
+/// all threads which could still be reading from it have finished.
+///
+/// The cost of this flexibility is that every operation pins the current
+/// thread, and fully read segments cannot be recycled. If values are
+/// only read after all writers have finished, prefer `SegBuffer`.
+pub struct SegBufferMpmc<T> {
+    raw: RawBuffer<T>,
+}
+
+impl<T> Default for SegBufferMpmc<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> SegBufferMpmc<T> {
+    /// Creates a new, empty `SegBufferMpmc<T>`.
+    pub fn new() -> Self {
+        Self {
+            raw: RawBuffer::new(),
+        }
+    }
+
+    pub(super) fn with_geometry(geometry: Geometry) -> Self {
+        Self {
+            raw: RawBuffer::new_in(geometry, Global),
+        }
+    }
+
+    /// Pushes an element to the buffer.
+    pub fn push(&self, value: T) {
+        let _guard = epoch::pin();
+        unsafe { self.raw.push(value) }
+    }
+
+    /// Pops an element from the front of the buffer.
+    ///
+    /// Returns `None` if the buffer is empty.
+    pub fn pop(&self) -> Option<T> {
+        let guard = epoch::pin();
+        unsafe { self.raw.pop_shared(&guard) }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::seg_buffer::SegmentAllocator;
+use std::alloc::{self, Layout};
+use std::collections::HashMap;
+use std::ptr::NonNull;
+use std::sync::Mutex;
+
+/// A block of memory cached by a `SegmentPool`.
+struct Block(NonNull<u8>);
+
+// Blocks are owned by the pool and not aliased.
+unsafe impl Send for Block {}
+
+/// A `SegmentAllocator` which keeps freed segments for reuse.
+///
+/// Share one pool between many buffers of the same element type, through
+/// `&SegmentPool` or `Arc<SegmentPool>`, so that segments freed by one buffer
+/// are reused by the others instead of going back to the global allocator.
+/// Since segments of the same capacity have the same layout, this works best
+/// when the buffers also share their segment geometry.
+///
+/// Cached memory is returned to the global allocator by `clear`
+/// or when the pool is dropped.
+#[derive(Default)]
+pub struct SegmentPool {
+    free: Mutex<HashMap<Layout, Vec<Block>>>,
+}
+
+impl SegmentPool {
+    /// Creates an empty pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of freed segments cached for reuse.
+    pub fn cached(&self) -> usize {
+        self.free.lock().unwrap().values().map(Vec::len).sum()
+    }
+
+    /// Returns all cached segments to the global allocator.
+    pub fn clear(&self) {
+        let free = std::mem::take(&mut *self.free.lock().unwrap());
+
+        for (layout, blocks) in free {
+            for block in blocks {
+                unsafe { alloc::dealloc(block.0.as_ptr(), layout) }
+            }
+        }
+    }
+}
+
+unsafe impl SegmentAllocator for SegmentPool {
+    fn allocate(&self, layout: Layout) -> *mut u8 {
+        let cached = self
+            .free
+            .lock()
+            .unwrap()
+            .get_mut(&layout)
+            .and_then(Vec::pop);
+
+        match cached {
+            Some(block) => block.0.as_ptr(),
+            None => unsafe { alloc::alloc(layout) },
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        let block = Block(NonNull::new_unchecked(ptr));
+
+        self.free
+            .lock()
+            .unwrap()
+            .entry(layout)
+            .or_default()
+            .push(block);
+    }
+
+    fn can_free(&self, _other: &Self) -> bool {
+        // Every pool takes its memory from the global allocator.
+        true
+    }
+}
+
+impl Drop for SegmentPool {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
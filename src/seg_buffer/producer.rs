@@ -0,0 +1,45 @@
+use crate::seg_buffer::raw::Reservation;
+use crate::seg_buffer::{Global, SegmentAllocator};
+use crate::SegBuffer;
+
+/// Number of slots a `Producer` reserves at once.
+const BLOCK_SIZE: usize = 32;
+
+/// A handle for pushing many values into a `SegBuffer` from one thread.
+///
+/// Instead of claiming one slot per push, a producer reserves blocks of
+/// slots and fills them locally, so that the shared counter in the head
+/// segment is touched once per block rather than once per value.
+///
+/// Values pushed through one producer appear in the buffer in the order
+/// they were pushed, but may be interleaved with values from other threads.
+/// Slots which are still reserved when the producer is dropped are released
+/// if possible, or else skipped by readers.
+///
+/// Returned by `SegBuffer::producer`.
+pub struct Producer<'a, T, A: SegmentAllocator = Global> {
+    buffer: &'a SegBuffer<T, A>,
+    reservation: Option<Reservation<T>>,
+}
+
+impl<'a, T, A: SegmentAllocator> Producer<'a, T, A> {
+    pub(super) fn new(buffer: &'a SegBuffer<T, A>) -> Self {
+        Self {
+            buffer,
+            reservation: None,
+        }
+    }
+
+    /// Pushes an element to the buffer.
+    pub fn push(&mut self, value: T) {
+        let reservation = match &mut self.reservation {
+            Some(reservation) if !reservation.is_full() => reservation,
+            reservation => {
+                *reservation = Some(unsafe { self.buffer.raw.reserve(BLOCK_SIZE) });
+                reservation.as_mut().unwrap()
+            }
+        };
+
+        unsafe { reservation.write(value) }
+    }
+}
@@ -1,25 +1,49 @@
-use std::cell::UnsafeCell;
-use std::cmp::min;
-use std::mem::MaybeUninit;
-use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
-use std::{iter, ptr};
+use crate::seg_buffer::metrics::Metrics;
+#[cfg(feature = "std")]
+use crate::seg_buffer::sync::epoch::Guard;
+#[cfg(loom)]
+use crate::seg_buffer::sync::AtomicMut;
+use crate::seg_buffer::sync::{
+    AtomicBool, AtomicPtr, AtomicU8, AtomicUsize, Backoff, Ordering, SlotCells,
+};
+use crate::seg_buffer::{Global, RetentionPolicy, SegmentAllocator};
+use alloc::alloc::{handle_alloc_error, Layout};
+use alloc::vec::Vec;
+use core::cmp::{self, min};
+use core::marker::PhantomData;
+use core::ops::DerefMut;
+use core::{mem, ptr, slice};
+use crossbeam_utils::CachePadded;
 
-/// Capacity of the first segment in the buffer.
+/// Default capacity of the first segment in the buffer.
 const STARTING_SIZE: usize = 64;
-/// Max capacity of a segment in the buffer.
+/// Default max capacity of a segment in the buffer.
 const MAX_SIZE: usize = 262_144;
+/// Default factor by which segment capacity grows.
+const GROWTH_FACTOR: usize = 2;
+
+/// Slot state: the slot has not been written yet.
+const EMPTY: u8 = 0;
+/// Slot state: the slot holds a fully written value.
+const WRITTEN: u8 = 1;
+/// Slot state: the slot was claimed but will never be written,
+/// e.g. because a reservation was abandoned.
+const SKIPPED: u8 = 2;
 
 /// A segment in the buffer.
+///
+/// Segments are allocated by `new_segment` as a single
+/// block of memory: this header is followed by the array
+/// of slot states and then the array of values.
+#[repr(C)]
 struct Segment<T> {
-    /// Pointer to the next segment in the list, or `ptr::null`
-    /// if it does not exist.
-    next: AtomicPtr<Segment<T>>,
-    /// Capacity of this segment.
-    capacity: usize,
     /// Index of the next value to write into this
     /// segment. Note that this value may exceed
     /// `capacity`: if so, the buffer has been fully written.
-    front: AtomicUsize,
+    ///
+    /// Padded to its own cache line, since it is
+    /// written by every producer.
+    front: CachePadded<AtomicUsize>,
     /// Index of the next value to read from this
     /// segment.
     ///
@@ -28,136 +52,1324 @@ struct Segment<T> {
     /// If the value is greater than or equal to `front`,
     /// then there are no values in this segment remaining
     /// to read.
-    back: AtomicUsize,
-    /// Array of values in this segment.
+    ///
+    /// Padded to its own cache line, so that consumers
+    /// do not contend with producers.
+    back: CachePadded<AtomicUsize>,
+    /// Pointer to the next segment in the list, or `ptr::null`
+    /// if it does not exist.
+    next: AtomicPtr<Segment<T>>,
+    /// Pointer to the previous segment in the list.
+    ///
+    /// Set before the segment is linked, and only read or updated
+    /// through `&mut RawBuffer`. Not meaningful for the tail.
+    prev: *mut Segment<T>,
+    /// Capacity of this segment.
+    capacity: usize,
+    /// Number of slots in this segment marked `SKIPPED`.
+    holes: AtomicUsize,
+    /// Whether a producer has taken responsibility for
+    /// linking the segment which follows this one.
+    ///
+    /// If set, `next` is either non-null or about to be set.
+    grown: AtomicBool,
+    /// Publication state of each slot, stored after the header.
+    ///
+    /// `push` stores `WRITTEN` with `Release` ordering only
+    /// after the value has been written, so readers
+    /// running concurrently with producers can tell claimed
+    /// slots apart from published ones.
+    ///
+    /// This array has length `self.capacity`.
+    states: *mut AtomicU8,
+    /// Array of values in this segment, stored after the states.
     ///
     /// This array has length `self.capacity`.
-    array: Vec<UnsafeCell<MaybeUninit<T>>>,
+    values: *mut T,
+    /// Tracks accesses to `values` when running under loom.
+    cells: SlotCells,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Segment<T> {
+    /// Returns the layout of a segment with the given capacity,
+    /// along with the offsets of its states and values.
+    fn layout(capacity: usize) -> (Layout, usize, usize) {
+        let header = Layout::new::<Self>();
+        let (layout, states) = header
+            .extend(Layout::array::<AtomicU8>(capacity).unwrap())
+            .unwrap();
+        let (layout, values) = layout
+            .extend(Layout::array::<T>(capacity).unwrap())
+            .unwrap();
+
+        (layout.pad_to_align(), states, values)
+    }
+
+    fn state(&self, index: usize) -> &AtomicU8 {
+        debug_assert!(index < self.capacity);
+        unsafe { &*self.states.add(index) }
+    }
+
+    fn state_mut(&mut self, index: usize) -> impl DerefMut<Target = u8> + '_ {
+        debug_assert!(index < self.capacity);
+        unsafe { (&mut *self.states.add(index)).get_mut() }
+    }
+
+    /// Returns a pointer to the value in the given slot.
+    fn slot(&self, index: usize) -> *mut T {
+        debug_assert!(index < self.capacity);
+        unsafe { self.values.add(index) }
+    }
+
+    /// Writes `value` into the given slot, which must have been
+    /// claimed and be empty.
+    unsafe fn write(&self, index: usize, value: T) {
+        self.cells
+            .with_mut(index..index + 1, || ptr::write(self.slot(index), value))
+    }
+
+    /// Moves the value out of the given slot, which must have been
+    /// claimed for reading and be written.
+    unsafe fn take(&self, index: usize) -> T {
+        self.cells
+            .with_mut(index..index + 1, || ptr::read(self.slot(index)))
+    }
+
+    /// Moves written values over skipped slots so that
+    /// the values between `back` and `front` are contiguous.
+    ///
+    /// This lowers `front`, so afterwards the segment may have
+    /// room left even if it is not the head.
+    fn compact(&mut self) {
+        if *self.holes.get_mut() == 0 {
+            return;
+        }
+
+        let back = min(self.capacity, *self.back.get_mut());
+        let front = min(self.capacity, *self.front.get_mut());
+
+        let mut write = back;
+        for read in back..front {
+            if *self.state_mut(read) == WRITTEN {
+                if read != write {
+                    unsafe {
+                        ptr::copy_nonoverlapping(self.slot(read), self.slot(write), 1);
+                    }
+                    *self.state_mut(write) = WRITTEN;
+                    *self.state_mut(read) = EMPTY;
+                }
+                write += 1;
+            } else {
+                *self.state_mut(read) = EMPTY;
+            }
+        }
+
+        *self.front.get_mut() = write;
+        *self.holes.get_mut() = 0;
+    }
+
+    /// Returns the number of slots between `back` and `front`,
+    /// which all hold values once the segment is compacted.
+    fn len(&mut self) -> usize {
+        min(self.capacity, *self.front.get_mut()) - min(self.capacity, *self.back.get_mut())
+    }
+
+    /// Drops the value in the given slot, leaving
+    /// a hole to be removed by `compact`.
+    fn remove(&mut self, index: usize) {
+        *self.state_mut(index) = SKIPPED;
+        *self.holes.get_mut() += 1;
+        unsafe { ptr::drop_in_place(self.slot(index)) }
+    }
+
+    /// Drops the values in this segment and resets it
+    /// so that it can be reused.
+    fn clear(&mut self) {
+        let front = min(self.capacity, *self.front.get_mut());
+        let back = min(self.capacity, *self.back.get_mut());
+
+        // Reset first, so that the segment is empty
+        // even if dropping a value panics.
+        *self.front.get_mut() = 0;
+        *self.back.get_mut() = 0;
+        *self.holes.get_mut() = 0;
+        *self.grown.get_mut() = false;
+
+        self.drop_range(back, front);
+    }
+
+    /// Drops the values between `back` and `front`.
+    fn drop_values(&mut self) {
+        let front = min(self.capacity, *self.front.get_mut());
+        let back = min(self.capacity, *self.back.get_mut());
+
+        self.drop_range(back, front);
+    }
+
+    /// Drops the values in the slots from `start` to `end`.
+    ///
+    /// If dropping a value panics, the remaining values
+    /// are still dropped before the panic continues.
+    fn drop_range(&mut self, start: usize, end: usize) {
+        let this: *mut Self = self;
+
+        for i in start..end {
+            let written = *self.state_mut(i) == WRITTEN;
+            *self.state_mut(i) = EMPTY;
+
+            if written {
+                let rest = OnUnwind(|| unsafe { (*this).drop_range(i + 1, end) });
+                unsafe { ptr::drop_in_place(self.slot(i)) }
+                mem::forget(rest);
+            }
+        }
+    }
 }
 
-impl<T> Drop for Segment<T> {
-    fn drop(&mut self) {
-        let front = min(self.capacity, *self.front.get_mut());
-        let back = min(self.capacity, *self.back.get_mut());
+/// Runs a closure when dropped.
+///
+/// Guards are usually passed to `mem::forget` once the code they guard
+/// has finished, so that the closure only runs if that code panics.
+/// This is used to finish dropping values or freeing segments after
+/// the destructor of a value panics.
+struct OnUnwind<F: FnMut()>(F);
+
+impl<F: FnMut()> Drop for OnUnwind<F> {
+    fn drop(&mut self) {
+        (self.0)()
+    }
+}
+
+impl<T> Drop for Segment<T> {
+    fn drop(&mut self) {
+        self.drop_values();
+    }
+}
+
+/// Capacities of the segments allocated by a buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct Geometry {
+    /// Capacity of the first segment.
+    pub starting_capacity: usize,
+    /// Factor by which the capacity of each new segment grows.
+    pub growth_factor: usize,
+    /// Max capacity of a segment, unless a larger one
+    /// is needed to hold a contiguous run of values.
+    pub max_capacity: usize,
+}
+
+impl Default for Geometry {
+    fn default() -> Self {
+        Self {
+            starting_capacity: STARTING_SIZE,
+            growth_factor: GROWTH_FACTOR,
+            max_capacity: MAX_SIZE,
+        }
+    }
+}
+
+impl Geometry {
+    /// Returns the capacity of the segment following one with the given capacity.
+    fn next_capacity(&self, capacity: usize) -> usize {
+        min(
+            self.max_capacity,
+            capacity.saturating_mul(self.growth_factor),
+        )
+    }
+}
+
+/// The list of segments backing a buffer.
+///
+/// Segments before the head may be partially filled, e.g.
+/// after compaction. Readers move on to the next segment
+/// once they reach `min(front, capacity)` of any segment
+/// other than the head.
+pub struct RawBuffer<T, A: SegmentAllocator = Global> {
+    /// Pointer to the head segment.
+    ///
+    /// Do note that segments may exist past this
+    /// head as linked by the `next` pointer.
+    /// The head is only the segment to which values
+    /// are currently written.
+    ///
+    /// This value must never be null.
+    head: AtomicPtr<Segment<T>>,
+    /// Pointer to the tail segment.
+    ///
+    /// This value must never be null.
+    tail: AtomicPtr<Segment<T>>,
+    /// Whether a segment was inserted by `insert_after`, which cannot
+    /// update the `prev` link of the segment following it. The links
+    /// are repaired by `repair_prev` before they are next read.
+    stale_prev: AtomicBool,
+    /// Capacities of newly allocated segments.
+    geometry: Geometry,
+    /// Limits on the spare segments kept after the head.
+    retention: RetentionPolicy,
+    /// Allocator for segments.
+    allocator: A,
+    /// Event counters, which are empty without the `metrics` feature.
+    metrics: Metrics,
+}
+
+// Values pushed through `&RawBuffer` may be popped on another thread.
+// Callers of `snapshot` must also require `T: Sync`, since it hands
+// out shared references to values.
+unsafe impl<T: Send, A: SegmentAllocator + Send> Send for RawBuffer<T, A> {}
+unsafe impl<T: Send, A: SegmentAllocator + Sync> Sync for RawBuffer<T, A> {}
+
+impl<T> RawBuffer<T> {
+    pub fn new() -> Self {
+        Self::new_in(Geometry::default(), Global)
+    }
+
+    /// Removes a value from the start of the buffer while other
+    /// threads may be pushing or popping.
+    ///
+    /// Unlike `pop`, fully read segments are not recycled. They are
+    /// unlinked from the list and released through `guard` once no
+    /// pinned reader or writer can still observe them.
+    ///
+    /// # Safety
+    /// Every concurrent `push` and `pop_shared` must run while pinned,
+    /// and `pop` may not be used on the same buffer.
+    #[cfg(feature = "std")]
+    pub unsafe fn pop_shared(&self, guard: &Guard) -> Option<T> {
+        loop {
+            let tail = self.tail.load(Ordering::Acquire);
+            let segment = &*tail;
+
+            let front = segment.front.load(Ordering::Acquire);
+            let back = segment.back.load(Ordering::Acquire);
+
+            if back >= segment.capacity {
+                // Every slot has been claimed by a reader, so the segment
+                // can be unlinked once its successor exists.
+                let next = segment.next.load(Ordering::Acquire);
+                if next.is_null() {
+                    return None;
+                }
+
+                // Producers must not be left pointing at the segment either.
+                let _ = self
+                    .head
+                    .compare_exchange(tail, next, Ordering::AcqRel, Ordering::Acquire);
+                if self
+                    .tail
+                    .compare_exchange(tail, next, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+                {
+                    guard.defer_unchecked(move || free_segment(tail, &Global));
+                }
+                continue;
+            }
+
+            let index = if front >= segment.capacity {
+                // Every slot has been claimed by a producer, so readers
+                // can claim slots without checking against `front`.
+                let index = segment.back.fetch_add(1, Ordering::AcqRel);
+                if index >= segment.capacity {
+                    continue;
+                }
+                index
+            } else if back >= front {
+                return None;
+            } else if segment
+                .back
+                .compare_exchange_weak(back, back + 1, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                back
+            } else {
+                continue;
+            };
+
+            // The producer which claimed the slot may still be writing to it.
+            let backoff = Backoff::new();
+            let state = loop {
+                match segment.state(index).load(Ordering::Acquire) {
+                    EMPTY => backoff.snooze(),
+                    state => break state,
+                }
+            };
+
+            // As in `pop`, the slot is left empty, so that it does not look
+            // written once the segment is recycled under `&mut` access.
+            segment.state(index).store(EMPTY, Ordering::Relaxed);
+
+            if state == WRITTEN {
+                return Some(segment.take(index));
+            }
+        }
+    }
+}
+
+impl<T, A: SegmentAllocator> RawBuffer<T, A> {
+    pub fn new_in(geometry: Geometry, allocator: A) -> Self {
+        let head = new_segment(geometry.starting_capacity, &allocator);
+        let metrics = Metrics::new();
+        metrics.segment_allocated();
+        Self {
+            head: AtomicPtr::new(head),
+            tail: AtomicPtr::new(head),
+            stale_prev: AtomicBool::new(false),
+            geometry,
+            retention: RetentionPolicy::default(),
+            allocator,
+            metrics,
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Allocates a segment with the given capacity using the buffer's allocator.
+    fn allocate_segment(&self, capacity: usize) -> *mut Segment<T> {
+        self.metrics.segment_allocated();
+        new_segment(capacity, &self.allocator)
+    }
+
+    pub fn retention(&self) -> RetentionPolicy {
+        self.retention
+    }
+
+    /// Sets the retention policy, freeing spare segments beyond its limits.
+    pub fn set_retention(&mut self, retention: RetentionPolicy) {
+        self.retention = retention;
+        self.trim_spares();
+    }
+
+    /// Marks the unused slots at the end of every segment before the
+    /// head as skipped, raising its `front` to its capacity.
+    ///
+    /// `append`, `retain` and the like can leave such gaps, which
+    /// `pop_shared` would otherwise take for the end of the buffer.
+    #[cfg(feature = "std")]
+    pub fn skip_unused_slots(&mut self) {
+        let head = *self.head.get_mut();
+        let mut segment = *self.tail.get_mut();
+        unsafe {
+            while segment != head {
+                let current = &mut *segment;
+                let front = *current.front.get_mut();
+                if front < current.capacity {
+                    for index in front..current.capacity {
+                        *current.state_mut(index) = SKIPPED;
+                    }
+                    *current.holes.get_mut() += current.capacity - front;
+                    *current.front.get_mut() = current.capacity;
+                }
+                segment = *current.next.get_mut();
+            }
+        }
+    }
+
+    /// Frees all spare segments after the head.
+    pub fn shrink_to_fit(&mut self) {
+        self.free_spares(0, 0);
+    }
+
+    /// Drops all values in the buffer.
+    ///
+    /// Every segment becomes a spare segment, subject
+    /// to the retention policy.
+    pub fn clear(&mut self) {
+        let head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+
+        *self.head.get_mut() = tail;
+        unsafe { clear_segments(tail, head) }
+        self.trim_spares();
+    }
+
+    /// Calls `f` on each segment from the tail up to and including the head.
+    fn for_each_segment<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut Segment<T>),
+    {
+        let head = *self.head.get_mut();
+        let mut segment = *self.tail.get_mut();
+        loop {
+            let current = unsafe { &mut *segment };
+            f(current);
+            if segment == head {
+                break;
+            }
+            segment = *current.next.get_mut();
+        }
+    }
+
+    /// Moves all values to the end of `vec`, leaving this buffer empty.
+    pub fn drain_into(&mut self, vec: &mut Vec<T>) {
+        let len = self.iter().map(|slice| slice.len()).sum();
+        vec.reserve(len);
+
+        self.for_each_segment(|segment| {
+            let back = min(segment.capacity, *segment.back.get_mut());
+            let front = min(segment.capacity, *segment.front.get_mut());
+
+            unsafe {
+                let end = vec.as_mut_ptr().add(vec.len());
+                ptr::copy_nonoverlapping(segment.slot(back), end, front - back);
+                vec.set_len(vec.len() + front - back);
+            }
+            for i in back..front {
+                *segment.state_mut(i) = EMPTY;
+            }
+            *segment.back.get_mut() = front;
+        });
+    }
+
+    /// Drops the values for which `f` returns `false`.
+    ///
+    /// Each segment is compacted in place once it has been visited.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.for_each_segment(|segment| {
+            let back = min(segment.capacity, *segment.back.get_mut());
+            let front = min(segment.capacity, *segment.front.get_mut());
+
+            for i in back..front {
+                if *segment.state_mut(i) == WRITTEN && !f(unsafe { &*segment.slot(i) }) {
+                    segment.remove(i);
+                }
+            }
+
+            segment.compact();
+        });
+    }
+
+    /// Drops consecutive values for which `same_bucket` returns `true`,
+    /// keeping the first of each run. `same_bucket` receives the value
+    /// to test and the last value kept.
+    pub fn dedup_by<F>(&mut self, mut same_bucket: F)
+    where
+        F: FnMut(&mut T, &mut T) -> bool,
+    {
+        // Values are only moved by compaction once all
+        // segments have been visited, so `last` stays valid.
+        let mut last: *mut T = ptr::null_mut();
+        self.for_each_segment(|segment| {
+            let back = min(segment.capacity, *segment.back.get_mut());
+            let front = min(segment.capacity, *segment.front.get_mut());
+
+            for i in back..front {
+                if *segment.state_mut(i) != WRITTEN {
+                    continue;
+                }
+
+                let value = segment.slot(i);
+                if !last.is_null() && unsafe { same_bucket(&mut *value, &mut *last) } {
+                    segment.remove(i);
+                } else {
+                    last = value;
+                }
+            }
+        });
+
+        self.for_each_segment(Segment::compact);
+    }
+
+    /// Merges the segments, each of which must already be sorted
+    /// by `compare`, into new segments.
+    ///
+    /// Each old segment is freed once all of its values have
+    /// been moved. Spare segments are kept.
+    pub fn merge_sorted<F>(&mut self, mut compare: F)
+    where
+        F: FnMut(&T, &T) -> cmp::Ordering,
+    {
+        let mut segments = Vec::new();
+        self.for_each_segment(|segment| {
+            segment.compact();
+            segments.push(segment as *mut Segment<T>);
+        });
+
+        let sorted = segments
+            .iter()
+            .filter(|&&segment| unsafe { (&mut *segment).len() } > 0)
+            .count();
+        if sorted <= 1 {
+            return;
+        }
+
+        let spares = unsafe { *(&mut *(*self.head.get_mut())).next.get_mut() };
+        let capacities: Vec<_> = segments
+            .iter()
+            .map(|&segment| unsafe { (*segment).capacity })
+            .collect();
+
+        // From here on, `merge` owns the segments and relinks them when dropped.
+        let mut merge = Merge {
+            buffer: self,
+            inputs: Vec::with_capacity(sorted),
+            outputs: Vec::with_capacity(segments.len()),
+            spares,
+        };
+        for segment in segments {
+            unsafe {
+                if (&mut *segment).len() > 0 {
+                    merge.inputs.push(segment);
+                } else {
+                    free_segment(segment, &merge.buffer.allocator);
+                }
+            }
+        }
+
+        // Min-heap of inputs ordered by their first value.
+        let less = |compare: &mut F, a: *mut Segment<T>, b: *mut Segment<T>| unsafe {
+            let a = &mut *a;
+            let index = *a.back.get_mut();
+            let a = &*a.slot(index);
+            let b = &mut *b;
+            let index = *b.back.get_mut();
+            let b = &*b.slot(index);
+            compare(a, b) == cmp::Ordering::Less
+        };
+        let mut heap: Vec<usize> = (0..merge.inputs.len()).collect();
+        for i in (0..heap.len() / 2).rev() {
+            sift_down(&mut heap, i, |a, b| {
+                less(&mut compare, merge.inputs[a], merge.inputs[b])
+            });
+        }
+
+        let mut output: *mut Segment<T> = ptr::null_mut();
+        while let Some(&i) = heap.first() {
+            unsafe {
+                let full = match output.as_mut() {
+                    Some(out) => *out.front.get_mut() >= out.capacity,
+                    None => true,
+                };
+                if full {
+                    // The old segments have room for every value.
+                    let capacity = capacities[merge.outputs.len()];
+                    output = merge.buffer.allocate_segment(capacity);
+                    merge.outputs.push(output);
+                }
+                let out = &mut *output;
+
+                let input = &mut *merge.inputs[i];
+                let index = *input.back.get_mut();
+                let position = *out.front.get_mut();
+                ptr::copy_nonoverlapping(input.slot(index), out.slot(position), 1);
+                *out.state_mut(position) = WRITTEN;
+                *out.front.get_mut() += 1;
+                *input.state_mut(index) = EMPTY;
+                *input.back.get_mut() += 1;
+
+                if input.len() == 0 {
+                    free_segment(input, &merge.buffer.allocator);
+                    merge.inputs[i] = ptr::null_mut();
+
+                    let last = heap.pop().unwrap();
+                    if heap.is_empty() {
+                        break;
+                    }
+                    heap[0] = last;
+                }
+            }
+
+            sift_down(&mut heap, 0, |a, b| {
+                less(&mut compare, merge.inputs[a], merge.inputs[b])
+            });
+        }
+    }
+
+    /// Returns whether segments may move between this buffer
+    /// and one using `allocator`.
+    fn can_exchange_segments(&self, allocator: &A) -> bool {
+        self.allocator.can_free(allocator) && allocator.can_free(&self.allocator)
+    }
+
+    /// Moves all segments of `other` after the head of this buffer.
+    ///
+    /// `other` keeps one spare segment, taken from its own spares,
+    /// from ours or newly allocated, to continue with.
+    ///
+    /// If the allocators cannot free each other's memory,
+    /// the values are moved one by one instead.
+    pub fn append(&mut self, other: &mut Self) {
+        self.repair_prev();
+        other.repair_prev();
+
+        if !self.can_exchange_segments(&other.allocator) {
+            while let Some(value) = unsafe { other.pop() } {
+                unsafe { self.push(value) };
+            }
+            return;
+        }
+
+        unsafe {
+            let other_tail = *other.tail.get_mut();
+            let other_head = *other.head.get_mut();
+            if other_tail == other_head && (&mut *other_head).len() == 0 {
+                return;
+            }
+
+            let head = *self.head.get_mut();
+            let mut spares = *(&mut *head).next.get_mut();
+            let other_spares = *(&mut *other_head).next.get_mut();
+
+            let segment = if !other_spares.is_null() {
+                other_spares
+            } else if !spares.is_null() {
+                let segment = spares;
+                spares = *(&mut *segment).next.get_mut();
+                *(&mut *segment).next.get_mut() = ptr::null_mut();
+                *(&mut *segment).grown.get_mut() = false;
+                segment
+            } else {
+                other.allocate_segment(other.geometry.starting_capacity)
+            };
+
+            *(&mut *head).next.get_mut() = other_tail;
+            (&mut *other_tail).prev = head;
+            *(&mut *other_head).next.get_mut() = spares;
+            if spares.is_null() {
+                *(&mut *other_head).grown.get_mut() = false;
+            } else {
+                (&mut *spares).prev = other_head;
+            }
+            *self.head.get_mut() = other_head;
+
+            (&mut *segment).prev = ptr::null_mut();
+            *other.tail.get_mut() = segment;
+            *other.head.get_mut() = segment;
+        }
+    }
+
+    /// Moves the values from index `at` onwards into a new buffer.
+    ///
+    /// Segments after the one containing `at` are moved as a whole,
+    /// along with the spare segments. Values after `at` in that segment
+    /// are moved into a new segment.
+    ///
+    /// If a clone of the allocator cannot free memory allocated by this
+    /// one, the values from `at` onwards are moved one by one instead.
+    ///
+    /// # Panics
+    /// Panics if `at` is greater than the number of values.
+    pub fn split_off(&mut self, at: usize) -> Self
+    where
+        A: Clone,
+    {
+        self.repair_prev();
+
+        if !self.can_exchange_segments(&self.allocator.clone()) {
+            let len: usize = self.iter().map(|slice| slice.len()).sum();
+            assert!(at <= len, "`at` out of bounds");
+
+            let mut moved = Vec::with_capacity(len - at);
+            for _ in at..len {
+                moved.extend(unsafe { self.pop_back() });
+            }
+            let other = self.empty_like();
+            for value in moved.into_iter().rev() {
+                unsafe { other.push(value) };
+            }
+            return other;
+        }
+
+        unsafe {
+            let head = *self.head.get_mut();
+
+            // Find the segment containing `at`.
+            let mut previous = ptr::null_mut();
+            let mut segment = *self.tail.get_mut();
+            let mut offset = at;
+            loop {
+                let current = &mut *segment;
+                current.compact();
+
+                let len = current.len();
+                if offset < len || segment == head {
+                    assert!(offset <= len, "`at` out of bounds");
+                    break;
+                }
+
+                offset -= len;
+                previous = segment;
+                segment = *current.next.get_mut();
+            }
+
+            let current = &mut *segment;
+            let len = current.len();
+
+            if offset == 0 && previous.is_null() {
+                // Every value is moved.
+                let other = self.empty_like();
+                return mem::replace(self, other);
+            }
+
+            if offset == len {
+                // Only at the end of the head: no value is moved.
+                return self.empty_like();
+            }
+
+            let (other_tail, new_head) = if offset == 0 {
+                (segment, previous)
+            } else {
+                // Move the values after `offset` into a new segment.
+                let start = min(*current.back.get_mut(), current.capacity) + offset;
+                let count = len - offset;
+
+                let moved = self.allocate_segment(cmp::max(count, self.geometry.starting_capacity));
+                let moved_ref = &mut *moved;
+                ptr::copy_nonoverlapping(current.slot(start), moved_ref.slot(0), count);
+                for i in 0..count {
+                    *moved_ref.state_mut(i) = WRITTEN;
+                    *current.state_mut(start + i) = EMPTY;
+                }
+                *moved_ref.front.get_mut() = count;
+                *current.front.get_mut() = start;
+
+                if segment == head {
+                    return self.with_chain(moved, moved);
+                }
+
+                let next = *current.next.get_mut();
+                *moved_ref.next.get_mut() = next;
+                if let Some(next) = next.as_mut() {
+                    next.prev = moved;
+                }
+                (moved, segment)
+            };
+
+            // Detach the segments from `other_tail` onwards.
+            (&mut *other_tail).prev = ptr::null_mut();
+            let new_head_ref = &mut *new_head;
+            *new_head_ref.next.get_mut() = ptr::null_mut();
+            *new_head_ref.grown.get_mut() = false;
+            *self.head.get_mut() = new_head;
+
+            self.with_chain(other_tail, head)
+        }
+    }
+
+    /// Creates an empty buffer with the same configuration as this one.
+    fn empty_like(&self) -> Self
+    where
+        A: Clone,
+    {
+        self.empty_with_capacity(self.geometry.starting_capacity)
+    }
+
+    /// Creates an empty buffer with the same configuration as this one,
+    /// whose only segment has the given capacity. The segment is allocated
+    /// by the new buffer's allocator, which is the one that frees it.
+    fn empty_with_capacity(&self, capacity: usize) -> Self
+    where
+        A: Clone,
+    {
+        let allocator = self.allocator.clone();
+        let segment = new_segment(capacity, &allocator);
+        let metrics = Metrics::new();
+        metrics.segment_allocated();
+        Self {
+            head: AtomicPtr::new(segment),
+            tail: AtomicPtr::new(segment),
+            stale_prev: AtomicBool::new(false),
+            geometry: self.geometry,
+            retention: self.retention,
+            allocator,
+            metrics,
+        }
+    }
+
+    /// Creates a buffer with the same configuration as this one,
+    /// which owns the segments from `tail` to `head` and any after it.
+    unsafe fn with_chain(&self, tail: *mut Segment<T>, head: *mut Segment<T>) -> Self
+    where
+        A: Clone,
+    {
+        Self {
+            head: AtomicPtr::new(head),
+            tail: AtomicPtr::new(tail),
+            stale_prev: AtomicBool::new(false),
+            geometry: self.geometry,
+            retention: self.retention,
+            allocator: self.allocator.clone(),
+            metrics: Metrics::new(),
+        }
+    }
+
+    /// Clones the published values into a new buffer with the same
+    /// configuration, keeping the capacity of each segment and the
+    /// values it holds. Spare segments are not cloned.
+    ///
+    /// Like `snapshot`, this stops at the first value which is still
+    /// being written, so it may run while other threads are pushing.
+    pub fn clone_segments(&self) -> Self
+    where
+        T: Clone,
+        A: Clone,
+    {
+        unsafe {
+            let mut source = self.tail.load(Ordering::Acquire);
+            let mut clone: Option<Self> = None;
+
+            loop {
+                let segment = &*source;
+
+                // Link the new segment before filling it, so that the
+                // values cloned so far are dropped if `clone` panics.
+                let target = match &mut clone {
+                    None => {
+                        let mut new = self.empty_with_capacity(segment.capacity);
+                        let target = *new.head.get_mut();
+                        clone = Some(new);
+                        target
+                    }
+                    Some(clone) => {
+                        let target = clone.allocate_segment(segment.capacity);
+                        let previous = *clone.head.get_mut();
+                        *(&mut *previous).next.get_mut() = target;
+                        *(&mut *previous).grown.get_mut() = true;
+                        (*target).prev = previous;
+                        *clone.head.get_mut() = target;
+                        target
+                    }
+                };
+                let target = &mut *target;
+
+                let back = min(segment.back.load(Ordering::Relaxed), segment.capacity);
+                let front = min(segment.front.load(Ordering::Acquire), segment.capacity);
+                let mut published = true;
+                for index in back..front {
+                    match segment.state(index).load(Ordering::Acquire) {
+                        WRITTEN => {
+                            let position = *target.front.get_mut();
+                            ptr::write(target.slot(position), (*segment.slot(index)).clone());
+                            *target.state_mut(position) = WRITTEN;
+                            *target.front.get_mut() += 1;
+                        }
+                        SKIPPED => (),
+                        _ => {
+                            published = false;
+                            break;
+                        }
+                    }
+                }
+
+                let next = segment.next.load(Ordering::Acquire);
+                if !published || source == self.head.load(Ordering::Acquire) || next.is_null() {
+                    break;
+                }
+                source = next;
+            }
+
+            clone.unwrap()
+        }
+    }
+
+    /// Frees spare segments beyond the limits of the retention policy.
+    fn trim_spares(&mut self) {
+        let RetentionPolicy {
+            max_segments,
+            max_bytes,
+        } = self.retention;
+
+        if max_segments.is_some() || max_bytes.is_some() {
+            self.free_spares(
+                max_segments.unwrap_or(usize::MAX),
+                max_bytes.unwrap_or(usize::MAX),
+            );
+        }
+    }
+
+    /// Keeps at most `max_segments` spare segments, taking at most
+    /// `max_bytes` in total, and frees the rest.
+    fn free_spares(&mut self, max_segments: usize, max_bytes: usize) {
+        let head = *self.head.get_mut();
+
+        let mut last = head;
+        let mut segments = 0;
+        let mut bytes = 0;
+        unsafe {
+            let excess = loop {
+                let next = *(&mut *last).next.get_mut();
+                if next.is_null() {
+                    break next;
+                }
+
+                segments += 1;
+                bytes += segment_bytes::<T>((&*next).capacity);
+                if segments > max_segments || bytes > max_bytes {
+                    *(&mut *last).next.get_mut() = ptr::null_mut();
+                    break next;
+                }
+
+                last = next;
+            };
+
+            // Done before freeing, so that producers still grow
+            // the buffer if the allocator panics.
+            let head = &mut *head;
+            if head.next.get_mut().is_null() {
+                *head.grown.get_mut() = false;
+            }
+
+            if !excess.is_null() {
+                free_segments(excess, &self.allocator);
+            }
+        }
+    }
+
+    /// Pushes a value onto the buffer.
+    ///
+    /// # Safety
+    /// Only other calls to `push` may execute concurrently.
+    pub unsafe fn push(&self, value: T) {
+        let (segment, index, _) = self.claim(1);
+
+        segment.write(index, value);
+        segment.state(index).store(WRITTEN, Ordering::Release);
+    }
+
+    /// Reserves a block of up to `len` consecutive slots.
+    ///
+    /// The reservation may be shorter than `len` if it
+    /// reaches the end of a segment.
+    ///
+    /// # Safety
+    /// Only other calls to `push` and `reserve` may execute concurrently
+    /// with this function or while the reservation is alive.
+    pub unsafe fn reserve(&self, len: usize) -> Reservation<T> {
+        let (segment, start, claimed) = self.claim(len);
+
+        Reservation::new(segment, start, claimed)
+    }
+
+    /// Reserves exactly `len` consecutive slots with a single claim.
+    ///
+    /// If the head segment does not have enough room left, the
+    /// slots claimed in it are given up and a dedicated segment
+    /// with room for at least `len` values is linked right after
+    /// it instead, becoming the new head.
+    ///
+    /// # Safety
+    /// Only other calls to `push` and `reserve` may execute concurrently
+    /// with this function or while the reservation is alive.
+    pub unsafe fn reserve_exact(&self, len: usize) -> Reservation<T> {
+        loop {
+            let head = &*self.head.load(Ordering::Acquire);
+
+            // `SeqCst`, so that `Waiters::notify` may follow the claim.
+            let position = head.front.fetch_add(len, Ordering::SeqCst);
+            // Skips the claimed slots if allocating a segment panics.
+            let claim = Reservation::new(head, position, position + len);
+            self.grow(head, position + len);
+
+            if position + len <= head.capacity {
+                return claim;
+            }
+
+            if position >= head.capacity {
+                // Nothing was claimed in this segment.
+                self.advance(head);
+                continue;
+            }
+
+            let capacity = cmp::max(len, self.geometry.next_capacity(head.capacity));
+            let segment = self.allocate_segment(capacity);
+            *(&mut *segment).front.get_mut() = len;
+            self.insert_after(head, segment);
+            let _ = self.head.compare_exchange(
+                head as *const _ as *mut _,
+                segment,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+
+            // Release or skip the part of the claim which fits into the head.
+            // This happens only once the new segment is linked, so that
+            // consumers cannot move past the head before then.
+            drop(claim);
+
+            return Reservation {
+                segment,
+                next: 0,
+                end: len,
+                claimed: len,
+            };
+        }
+    }
+
+    /// Claims `len` consecutive slot indices in the head segment,
+    /// returning the segment, the first claimed index, and the
+    /// value of `front` after the claim.
+    ///
+    /// The first index is always within the segment's capacity,
+    /// but the claim may extend past it.
+    unsafe fn claim(&self, len: usize) -> (&Segment<T>, usize, usize) {
+        loop {
+            let head = &*self.head.load(Ordering::Acquire);
+
+            // `SeqCst`, so that `Waiters::notify` may follow the claim.
+            let position = head.front.fetch_add(len, Ordering::SeqCst);
+            // Skips the claimed slots if allocating a segment panics,
+            // so that consumers do not wait for them.
+            let claim = Reservation::new(head, position, position + len);
+            self.grow(head, position + len);
+            mem::forget(claim);
+
+            if position < head.capacity {
+                break (head, position, position + len);
+            }
+
+            self.advance(head);
+        }
+    }
+
+    /// Called after a claim in the full segment `head` which
+    /// started past its end.
+    ///
+    /// The next segment is being allocated by the producer which crossed
+    /// the growth threshold, so wait until it is linked and then attempt
+    /// to set it as the new head. If that producer panicked before linking
+    /// it, `grown` has been reset and the next waiter allocates it instead.
+    ///
+    /// If waiting does not help, e.g. because that producer was preempted,
+    /// allocate and link the next segment here rather than block on it.
+    unsafe fn advance(&self, head: &Segment<T>) {
+        self.metrics.head_retry();
+
+        let backoff = Backoff::new();
+        let next = loop {
+            let next = head.next.load(Ordering::Acquire);
+            if !next.is_null() {
+                break next;
+            }
+            if backoff.is_completed() {
+                break self.link_next(head);
+            }
+            self.grow(head, head.capacity);
+            backoff.snooze();
+        };
+
+        let _ = self.head.compare_exchange(
+            head as *const _ as *mut _,
+            next,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+    }
+
+    /// Called after a claim in `segment` which moved `front` to `claimed`.
+    ///
+    /// Once a claim passes half of the segment's capacity, exactly one
+    /// producer allocates the following segment (unless a spare segment
+    /// is already linked), so that it is usually available before
+    /// the segment fills up.
+    unsafe fn grow(&self, segment: &Segment<T>, claimed: usize) {
+        if claimed <= segment.capacity / 2
+            || segment.grown.load(Ordering::Relaxed)
+            || segment.grown.swap(true, Ordering::AcqRel)
+        {
+            return;
+        }
+
+        // If allocating panics, let a waiting producer take over.
+        let reset = OnUnwind(|| segment.grown.store(false, Ordering::Release));
 
-        for i in back..front {
-            unsafe {
-                drop(ptr::read((&mut *self.array[i].get()).as_mut_ptr()));
+        if segment.next.load(Ordering::Acquire).is_null() {
+            let next = self.allocate_segment(self.geometry.next_capacity(segment.capacity));
+            self.append_segment(next);
+
+            if segment.next.load(Ordering::Relaxed) != next {
+                // Another segment was linked first; ours ends up as a spare.
+                self.metrics.segment_wasted();
             }
         }
+
+        mem::forget(reset);
     }
-}
 
-pub struct RawBuffer<T> {
-    /// Pointer to the head segment.
-    ///
-    /// Do note that segments may exist past this
-    /// head as linked by the `next` pointer.
-    /// The head is only the segment to which values
-    /// are currently written.
+    /// Allocates a segment and links it after `head`, returning the
+    /// segment which follows `head` afterwards.
     ///
-    /// This value must never be null.
-    head: AtomicPtr<Segment<T>>,
-    /// Pointer to the tail segment.
-    ///
-    /// This value must never be null.
-    tail: AtomicPtr<Segment<T>>,
-}
+    /// If another segment was linked first, ours ends up as a spare.
+    unsafe fn link_next(&self, head: &Segment<T>) -> *mut Segment<T> {
+        let segment = self.allocate_segment(self.geometry.next_capacity(head.capacity));
+        // The segment is not yet visible to other threads.
+        (*segment).prev = head as *const _ as *mut _;
 
-impl<T> RawBuffer<T> {
-    pub fn new() -> Self {
-        let head = new_segment(STARTING_SIZE);
-        Self {
-            head: AtomicPtr::new(head),
-            tail: AtomicPtr::new(head),
+        match head.next.compare_exchange(
+            ptr::null_mut(),
+            segment,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => segment,
+            Err(next) => {
+                self.append_segment(segment);
+                self.metrics.segment_wasted();
+                next
+            }
         }
     }
 
-    /// Pushes a value onto the buffer.
+    /// Removes a value from the start of the buffer.
     ///
     /// # Safety
-    /// Only other calls to `push` may execute concurrently.
-    pub unsafe fn push(&self, value: T) {
-        // Obtain a position in a segment.
+    /// Neither push operations or other pop operations may not run in parallel with this function.
+    pub unsafe fn pop(&mut self) -> Option<T> {
+        // No need for atomic operations, since we have unique access.
         let (segment, index) = loop {
-            let head = &mut *self.head.load(Ordering::Acquire);
+            let tail = *self.tail.get_mut();
+            let segment = &mut *tail;
 
-            let position = head.front.fetch_add(1, Ordering::AcqRel);
+            let index = *segment.back.get_mut();
+            let front = min(*segment.front.get_mut(), segment.capacity);
 
-            if position < head.capacity {
-                break (head, position);
-            } else {
-                // Position is past the end of the segment. We do the following:
-                // * If `head->next` is set, then there is another segment available.
-                // Attempt to set it as the new head and continue the loop.
-                // * Otherwise, there are no more available segments.
-                // We allocate a new one and traverse the list forward
-                // until there is a segment whose `next` pointer we can
-                // set to the new segment (i.e. the old value isn't null).
-                let next = head.next.load(Ordering::Acquire);
-                if !next.is_null() {
-                    self.head
-                        .compare_and_swap(head as *mut _, next, Ordering::AcqRel);
-                } else {
-                    // Allocate new segment.
-                    let new_segment = new_segment(min(MAX_SIZE, head.capacity * 2));
+            if index < front {
+                *segment.back.get_mut() += 1;
 
-                    self.append_segment(new_segment);
+                if *segment.state_mut(index) == WRITTEN {
+                    break (segment, index);
                 }
+
+                // Skipped slot: there is no value to read.
+                *segment.state_mut(index) = EMPTY;
+                continue;
+            }
+
+            if ptr::eq(*self.head.get_mut(), segment) {
+                if index >= segment.capacity {
+                    // The head was fully read; reuse it from the start.
+                    self.metrics.segment_recycled();
+                    *segment.back.get_mut() = 0;
+                    *segment.front.get_mut() = 0;
+                    *segment.holes.get_mut() = 0;
+                    *segment.grown.get_mut() = !segment.next.get_mut().is_null();
+                }
+                return None;
             }
+
+            self.metrics.segment_recycled();
+            *segment.back.get_mut() = 0;
+            *segment.front.get_mut() = 0;
+            *segment.holes.get_mut() = 0;
+            *segment.grown.get_mut() = false;
+            let next = *segment.next.get_mut();
+            (&mut *next).prev = ptr::null_mut();
+            *self.tail.get_mut() = next;
+            *segment.next.get_mut() = ptr::null_mut();
+            self.append_segment(segment);
+            self.trim_spares();
         };
 
-        // Write value into segment.
-        let ptr = (&mut *segment.array[index].get()).as_mut_ptr();
+        *segment.state_mut(index) = EMPTY;
 
-        ptr::write(ptr, value);
+        Some(segment.take(index))
     }
 
-    /// Removes a value from the start of the buffer.
+    /// Removes values from the start of the buffer into `dst`,
+    /// returning how many were removed.
     ///
     /// # Safety
     /// Neither push operations or other pop operations may not run in parallel with this function.
-    pub unsafe fn pop(&mut self) -> Option<T> {
-        // No need for atomic operations, since we have unique access.
-        let (segment, index) = loop {
-            let segment = &mut **self.tail.get_mut();
+    pub unsafe fn pop_into(&mut self, dst: &mut [T]) -> usize
+    where
+        T: Copy,
+    {
+        let mut read = 0;
+        while read < dst.len() {
+            let tail = *self.tail.get_mut();
+            let segment = &mut *tail;
 
-            let index = *segment.back.get_mut();
-            *segment.back.get_mut() += 1;
+            let back = *segment.back.get_mut();
+            let front = min(*segment.front.get_mut(), segment.capacity);
 
-            if index >= *segment.front.get_mut() {
-                *segment.back.get_mut() = *segment.front.get_mut();
-                return None;
+            // Copy the run of written values at the start of the tail at once.
+            let mut end = back;
+            while end < front && end - back < dst.len() - read && *segment.state_mut(end) == WRITTEN
+            {
+                *segment.state_mut(end) = EMPTY;
+                end += 1;
             }
 
-            if index >= segment.capacity {
-                *segment.back.get_mut() = 0;
-                *segment.front.get_mut() = 0;
-                if *self.head.get_mut() == segment as *mut _ {
-                    return None;
-                } else {
-                    *self.tail.get_mut() = *segment.next.get_mut();
-                    *segment.next.get_mut() = ptr::null_mut();
-                    self.append_segment(segment);
+            if end > back {
+                let count = end - back;
+                ptr::copy_nonoverlapping(segment.slot(back), dst.as_mut_ptr().add(read), count);
+                *segment.back.get_mut() = end;
+                read += count;
+                continue;
+            }
+
+            // Skipped slots and exhausted segments are handled by `pop`.
+            match self.pop() {
+                Some(value) => {
+                    dst[read] = value;
+                    read += 1;
                 }
-            } else {
-                break (segment, index);
+                None => break,
             }
-        };
+        }
+        read
+    }
+
+    /// Removes a value from the end of the buffer.
+    ///
+    /// Once the head segment is empty, the head moves back to the
+    /// previous segment and the empty one becomes a spare segment.
+    ///
+    /// # Safety
+    /// Neither push operations or other pop operations may not run in parallel with this function.
+    pub unsafe fn pop_back(&mut self) -> Option<T> {
+        self.repair_prev();
+
+        loop {
+            let head = *self.head.get_mut();
+            let segment = &mut *head;
+
+            let back = min(*segment.back.get_mut(), segment.capacity);
+            let front = min(*segment.front.get_mut(), segment.capacity);
+
+            if back < front {
+                let index = front - 1;
+                *segment.front.get_mut() = index;
+
+                if *segment.state_mut(index) == WRITTEN {
+                    *segment.state_mut(index) = EMPTY;
+                    return Some(segment.take(index));
+                }
 
-        let ptr = (&*segment.array[index].get()).as_ptr();
-        Some(ptr::read(ptr))
+                // Skipped slot: there is no value to read.
+                *segment.state_mut(index) = EMPTY;
+                continue;
+            }
+
+            if head == *self.tail.get_mut() {
+                return None;
+            }
+
+            self.metrics.segment_recycled();
+            *segment.back.get_mut() = 0;
+            *segment.front.get_mut() = 0;
+            *segment.holes.get_mut() = 0;
+            *segment.grown.get_mut() = !segment.next.get_mut().is_null();
+            *self.head.get_mut() = segment.prev;
+            self.trim_spares();
+        }
     }
 
     /// Returns a raw iterator over segments.
     ///
     /// # Safety
     /// Neither push operations or other pop operations may not run in parallel with this function.
-    pub fn iter(&mut self) -> RawIter<T> {
-        let tail = *self.tail.get_mut();
+    pub fn iter(&mut self) -> RawIter<'_, T> {
+        self.repair_prev();
+
         RawIter {
-            buffer: self,
-            segment: tail,
+            front: *self.tail.get_mut(),
+            back: *self.head.get_mut(),
+            _marker: PhantomData,
         }
     }
 
@@ -166,82 +1378,491 @@ impl<T> RawBuffer<T> {
     /// # Safety
     /// Neither push operations or other pop operations may not run in parallel with this function.
     #[cfg(feature = "rayon")]
-    pub fn par_iter(&mut self) -> ParRawIter<T> {
-        let tail = *self.tail.get_mut();
+    pub fn par_iter(&mut self) -> ParRawIter<'_, T> {
         ParRawIter {
-            buffer: self,
+            front: *self.tail.get_mut(),
+            back: *self.head.get_mut(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over published values which
+    /// may run concurrently with `push`.
+    ///
+    /// # Safety
+    /// Pop operations may not run in parallel with this function
+    /// or while the returned iterator is alive.
+    pub fn snapshot(&self) -> RawSnapshotIter<'_, T> {
+        let tail = self.tail.load(Ordering::Acquire);
+        RawSnapshotIter {
+            head: &self.head,
             segment: tail,
+            index: unsafe { (&*tail).back.load(Ordering::Relaxed) },
+        }
+    }
+
+    /// Links `segment` directly after `after`.
+    unsafe fn insert_after(&self, after: &Segment<T>, segment: *mut Segment<T>) {
+        let mut next = after.next.load(Ordering::Acquire);
+        loop {
+            // The segment is not yet visible to other threads.
+            (*segment).prev = after as *const _ as *mut _;
+            (*segment).next.store(next, Ordering::Relaxed);
+
+            match after
+                .next
+                .compare_exchange(next, segment, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(current) => next = current,
+            }
+        }
+
+        // Other threads may be linking segments after ours, so
+        // leave updating `next.prev` to `&mut` operations.
+        if !next.is_null() {
+            self.stale_prev.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Sets the `prev` link of every segment, if `insert_after`
+    /// has left some of them stale.
+    fn repair_prev(&mut self) {
+        if !*self.stale_prev.get_mut() {
+            return;
+        }
+        *self.stale_prev.get_mut() = false;
+
+        let mut segment = *self.tail.get_mut();
+        unsafe {
+            loop {
+                let next = *(&mut *segment).next.get_mut();
+                if next.is_null() {
+                    break;
+                }
+                (&mut *next).prev = segment;
+                segment = next;
+            }
         }
     }
 
     unsafe fn append_segment(&self, segment: *mut Segment<T>) {
         // Traverse to the end of the list and add the new segment.
         let mut head = self.head.load(Ordering::Acquire);
-        let mut next = ptr::null_mut::<Segment<T>>();
-        while !head.is_null() && {
-            next = (&*head)
-                .next
-                .compare_and_swap(ptr::null_mut(), segment, Ordering::AcqRel);
-            !next.is_null()
-        } {
-            head = next;
+        loop {
+            // The segment is not yet visible to other threads.
+            (*segment).prev = head;
+
+            match (&*head).next.compare_exchange(
+                ptr::null_mut(),
+                segment,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(next) => head = next,
+            }
         }
     }
 }
 
-impl<T> Drop for RawBuffer<T> {
+/// The state of `RawBuffer::merge_sorted`.
+///
+/// When dropped, even by a panic in the comparison function,
+/// the output segments and the remaining input segments
+/// are linked back into the buffer in that order.
+struct Merge<'a, T, A: SegmentAllocator> {
+    buffer: &'a mut RawBuffer<T, A>,
+    /// Old segments, or null once freed.
+    inputs: Vec<*mut Segment<T>>,
+    /// New segments, in order.
+    outputs: Vec<*mut Segment<T>>,
+    /// Spare segments to link after the new head.
+    spares: *mut Segment<T>,
+}
+
+impl<'a, T, A: SegmentAllocator> Drop for Merge<'a, T, A> {
     fn drop(&mut self) {
-        let mut tail = *self.tail.get_mut();
+        let mut segments = self
+            .outputs
+            .iter()
+            .chain(&self.inputs)
+            .copied()
+            .filter(|segment| !segment.is_null());
 
-        while !tail.is_null() {
-            unsafe {
-                let temp = *(&mut *tail).next.get_mut();
-                drop(Box::from_raw(tail));
-                tail = temp;
+        unsafe {
+            let tail = segments.next().unwrap();
+            (&mut *tail).prev = ptr::null_mut();
+
+            let mut head = tail;
+            for segment in segments {
+                *(&mut *head).next.get_mut() = segment;
+                *(&mut *head).grown.get_mut() = false;
+                (&mut *segment).prev = head;
+                head = segment;
+            }
+
+            *(&mut *head).next.get_mut() = self.spares;
+            if let Some(spare) = self.spares.as_mut() {
+                spare.prev = head;
+            } else {
+                *(&mut *head).grown.get_mut() = false;
             }
+
+            *self.buffer.tail.get_mut() = tail;
+            *self.buffer.head.get_mut() = head;
         }
     }
 }
 
-fn new_segment<T>(capacity: usize) -> *mut Segment<T> {
-    let boxed = Box::new(Segment {
-        next: AtomicPtr::new(ptr::null_mut()),
-        capacity,
-        front: AtomicUsize::new(0),
-        back: AtomicUsize::new(0),
-        array: iter::repeat_with(|| UnsafeCell::new(MaybeUninit::uninit()))
-            .take(capacity)
-            .collect(),
-    });
+/// Restores the order of a binary min-heap after the
+/// element at `index` may have become larger.
+fn sift_down<F>(heap: &mut [usize], mut index: usize, mut less: F)
+where
+    F: FnMut(usize, usize) -> bool,
+{
+    loop {
+        let left = 2 * index + 1;
+        let right = left + 1;
+
+        let mut smallest = index;
+        if left < heap.len() && less(heap[left], heap[smallest]) {
+            smallest = left;
+        }
+        if right < heap.len() && less(heap[right], heap[smallest]) {
+            smallest = right;
+        }
+        if smallest == index {
+            return;
+        }
+
+        heap.swap(index, smallest);
+        index = smallest;
+    }
+}
+
+impl<T, A: SegmentAllocator> Drop for RawBuffer<T, A> {
+    fn drop(&mut self) {
+        unsafe { free_segments(*self.tail.get_mut(), &self.allocator) }
+    }
+}
+
+/// Clears the segments from `segment` up to and including `last`.
+///
+/// If dropping a value panics, the remaining segments
+/// are still cleared before the panic continues.
+unsafe fn clear_segments<T>(mut segment: *mut Segment<T>, last: *mut Segment<T>) {
+    loop {
+        let current = &mut *segment;
+        if segment == last {
+            current.clear();
+            return;
+        }
+
+        let next = *current.next.get_mut();
+        let rest = OnUnwind(|| clear_segments(next, last));
+        current.clear();
+        mem::forget(rest);
+        segment = next;
+    }
+}
+
+/// Frees `segment` and all segments linked after it.
+///
+/// If dropping a value panics, the remaining segments
+/// are still freed before the panic continues.
+unsafe fn free_segments<T, A: SegmentAllocator>(mut segment: *mut Segment<T>, allocator: &A) {
+    while !segment.is_null() {
+        let next = *(&mut *segment).next.get_mut();
+        let rest = OnUnwind(|| free_segments(next, allocator));
+        free_segment(segment, allocator);
+        mem::forget(rest);
+        segment = next;
+    }
+}
+
+/// Returns the number of bytes of storage used by the slots
+/// of a segment with the given capacity.
+fn segment_bytes<T>(capacity: usize) -> usize {
+    capacity * (mem::size_of::<T>() + mem::size_of::<AtomicU8>())
+}
+
+/// Returns the capacity of a segment whose slots take
+/// up to `bytes` bytes of storage, which is at least 1.
+pub fn capacity_for_bytes<T>(bytes: usize) -> usize {
+    cmp::max(1, bytes / segment_bytes::<T>(1))
+}
+
+fn new_segment<T, A: SegmentAllocator>(capacity: usize, allocator: &A) -> *mut Segment<T> {
+    let (layout, states, values) = Segment::<T>::layout(capacity);
+
+    unsafe {
+        let ptr = allocator.allocate(layout);
+        if ptr.is_null() {
+            handle_alloc_error(layout);
+        }
+
+        // Values are left uninitialized.
+        let states = ptr.add(states) as *mut AtomicU8;
+        for i in 0..capacity {
+            ptr::write(states.add(i), AtomicU8::new(EMPTY));
+        }
 
-    Box::into_raw(boxed)
+        let segment = ptr as *mut Segment<T>;
+        ptr::write(
+            segment,
+            Segment {
+                front: CachePadded::new(AtomicUsize::new(0)),
+                back: CachePadded::new(AtomicUsize::new(0)),
+                next: AtomicPtr::new(ptr::null_mut()),
+                prev: ptr::null_mut(),
+                capacity,
+                holes: AtomicUsize::new(0),
+                grown: AtomicBool::new(false),
+                states,
+                values: ptr.add(values) as *mut T,
+                cells: SlotCells::new(capacity),
+                _marker: PhantomData,
+            },
+        );
+
+        segment
+    }
+}
+
+/// Drops the values in `segment` and frees its memory.
+unsafe fn free_segment<T, A: SegmentAllocator>(segment: *mut Segment<T>, allocator: &A) {
+    let capacity = (*segment).capacity;
+    let (layout, _, _) = Segment::<T>::layout(capacity);
+
+    let states = (*segment).states;
+
+    // The memory is released even if dropping a value panics.
+    let _free = OnUnwind(|| {
+        ptr::drop_in_place(ptr::slice_from_raw_parts_mut(states, capacity));
+        allocator.deallocate(segment as *mut u8, layout);
+    });
+    ptr::drop_in_place(segment);
 }
 
+/// Iterates over the values of each segment from the tail
+/// up to and including the head.
 pub struct RawIter<'a, T> {
-    #[allow(dead_code)]
-    buffer: &'a RawBuffer<T>,
-    segment: *mut Segment<T>,
+    /// The next segment to yield from the front,
+    /// or null once all segments have been yielded.
+    front: *mut Segment<T>,
+    /// The next segment to yield from the back.
+    back: *mut Segment<T>,
+    _marker: PhantomData<&'a mut T>,
 }
 
 impl<'a, T> Iterator for RawIter<'a, T> {
     type Item = &'a mut [T];
 
     fn next(&mut self) -> Option<Self::Item> {
-        let segment = unsafe { self.segment.as_mut()? };
+        let segment = self.front;
+        if segment.is_null() {
+            return None;
+        }
+
+        if segment == self.back {
+            self.front = ptr::null_mut();
+        } else {
+            self.front = unsafe { *(&mut *segment).next.get_mut() };
+        }
 
-        let start = min(*segment.back.get_mut(), segment.capacity);
-        let end = min(*segment.front.get_mut(), segment.capacity);
+        Some(unsafe { segment_slice(segment) })
+    }
+}
 
-        let uninit_slice = &mut segment.array[start..end];
+impl<'a, T> DoubleEndedIterator for RawIter<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front.is_null() {
+            return None;
+        }
 
-        // Sound because both UnsafeCell and MaybeUninit are repr(transparent).
-        let slice = unsafe {
-            std::mem::transmute::<&mut [UnsafeCell<MaybeUninit<T>>], &mut [T]>(uninit_slice)
-        };
+        let segment = self.back;
+        if segment == self.front {
+            self.front = ptr::null_mut();
+        } else {
+            self.back = unsafe { (*segment).prev };
+        }
+
+        Some(unsafe { segment_slice(segment) })
+    }
+}
+
+/// Compacts `segment` and returns a slice of its values.
+///
+/// # Safety
+/// The segment must be borrowed mutably for `'a`.
+unsafe fn segment_slice<'a, T>(segment: *mut Segment<T>) -> &'a mut [T] {
+    let segment = &mut *segment;
+    segment.compact();
+
+    let start = min(*segment.back.get_mut(), segment.capacity);
+    let end = min(*segment.front.get_mut(), segment.capacity);
+
+    // Sound because every slot in this range holds a written value.
+    slice::from_raw_parts_mut(segment.values.add(start), end - start)
+}
+
+/// A block of consecutive slots in one segment
+/// claimed by `RawBuffer::reserve`.
+///
+/// Slots which are not written before the reservation is
+/// dropped are returned to the segment if no other slot was
+/// claimed after them, or else marked as skipped.
+pub struct Reservation<T> {
+    segment: *const Segment<T>,
+    /// Index of the next slot to write.
+    next: usize,
+    /// End of the usable slots, which is at most the capacity of the segment.
+    end: usize,
+    /// Value of `front` right after the claim.
+    claimed: usize,
+}
+
+impl<T> Reservation<T> {
+    /// Covers the slots of `segment` claimed by moving its `front` from
+    /// `position` to `claimed`, up to the capacity of the segment.
+    fn new(segment: &Segment<T>, position: usize, claimed: usize) -> Self {
+        Reservation {
+            segment,
+            next: min(position, segment.capacity),
+            end: min(claimed, segment.capacity),
+            claimed,
+        }
+    }
+
+    /// Returns whether every slot in the reservation has been written.
+    pub fn is_full(&self) -> bool {
+        self.next == self.end
+    }
+
+    /// Writes a value into the next slot of the reservation.
+    ///
+    /// # Safety
+    /// The reservation must not be full.
+    pub unsafe fn write(&mut self, value: T) {
+        debug_assert!(!self.is_full());
+        let segment = &*self.segment;
+
+        segment.write(self.next, value);
+        segment.state(self.next).store(WRITTEN, Ordering::Release);
+        self.next += 1;
+    }
+
+    /// Copies `values` into the next slots of the reservation.
+    ///
+    /// # Safety
+    /// The reservation must have at least `values.len()` slots left.
+    pub unsafe fn write_slice(&mut self, values: &[T])
+    where
+        T: Copy,
+    {
+        debug_assert!(self.end - self.next >= values.len());
+        let segment = &*self.segment;
+
+        let slots = self.next..self.next + values.len();
+        segment.cells.with_mut(slots, || {
+            ptr::copy_nonoverlapping(values.as_ptr(), segment.slot(self.next), values.len())
+        });
+
+        for index in self.next..self.next + values.len() {
+            segment.state(index).store(WRITTEN, Ordering::Release);
+        }
+        self.next += values.len();
+    }
+}
+
+impl<T> Drop for Reservation<T> {
+    fn drop(&mut self) {
+        if self.is_full() {
+            return;
+        }
 
-        self.segment = *segment.next.get_mut();
+        let segment = unsafe { &*self.segment };
 
-        Some(slice)
+        // Return the unused slots if nobody has claimed any slot after them.
+        // A claim which reached the end of the segment may have let the head
+        // and readers move past it, so `front` must stay there and the slots
+        // are skipped instead.
+        if self.claimed < segment.capacity
+            && segment
+                .front
+                .compare_exchange(self.claimed, self.next, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        {
+            return;
+        }
+
+        for index in self.next..self.end {
+            segment.state(index).store(SKIPPED, Ordering::Release);
+        }
+        segment
+            .holes
+            .fetch_add(self.end - self.next, Ordering::Relaxed);
+    }
+}
+
+pub struct RawSnapshotIter<'a, T> {
+    /// The head pointer of the buffer.
+    head: &'a AtomicPtr<Segment<T>>,
+    segment: *mut Segment<T>,
+    /// Index of the next slot to read in `segment`.
+    index: usize,
+}
+
+impl<'a, T> Iterator for RawSnapshotIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let segment = unsafe { self.segment.as_ref()? };
+
+            let front = min(segment.front.load(Ordering::Acquire), segment.capacity);
+
+            if self.index < front {
+                let index = self.index;
+                self.index += 1;
+
+                match segment.state(index).load(Ordering::Acquire) {
+                    WRITTEN => {
+                        let value = segment.cells.with(index..index + 1, || segment.slot(index));
+                        return Some(unsafe { &*value });
+                    }
+                    SKIPPED => continue,
+                    _ => {
+                        // The slot is still being written by a producer.
+                        // The snapshot ends at the first unpublished value so
+                        // that it is always a prefix of the buffer.
+                        self.segment = ptr::null_mut();
+                        return None;
+                    }
+                }
+            }
+
+            if self.index < segment.capacity {
+                // Producers may have filled the segment and moved the head
+                // since `front` was loaded. Load the head first: once it has
+                // moved past the segment, `front` covers every value in it.
+                let is_head = self.head.load(Ordering::Acquire) == self.segment;
+                if self.index < min(segment.front.load(Ordering::Acquire), segment.capacity) {
+                    continue;
+                }
+                if is_head {
+                    // Reached the end of the head segment.
+                    self.segment = ptr::null_mut();
+                    return None;
+                }
+            }
+
+            self.segment = segment.next.load(Ordering::Acquire);
+            if let Some(next) = unsafe { self.segment.as_ref() } {
+                self.index = next.back.load(Ordering::Relaxed);
+            }
+        }
     }
 }
 
@@ -249,13 +1870,16 @@ impl<'a, T> Iterator for RawIter<'a, T> {
 pub use self::rayon::*;
 #[cfg(feature = "rayon")]
 mod rayon {
-    use crate::seg_buffer::raw::{RawBuffer, RawIter, Segment};
+    use crate::seg_buffer::raw::{RawIter, Segment};
+    use core::marker::PhantomData;
     use rayon::iter::plumbing::{Consumer, Folder, UnindexedConsumer, UnindexedProducer};
     use rayon::iter::{plumbing, ParallelIterator};
 
+    /// Splits the segments from `front` to `back` between threads.
     pub struct ParRawIter<'a, T> {
-        pub(super) buffer: &'a RawBuffer<T>,
-        pub(super) segment: *mut Segment<T>,
+        pub(super) front: *mut Segment<T>,
+        pub(super) back: *mut Segment<T>,
+        pub(super) _marker: PhantomData<&'a mut T>,
     }
 
     unsafe impl<'a, T> Send for ParRawIter<'a, T> where T: Send {}
@@ -275,13 +1899,13 @@ mod rayon {
     }
 
     impl<'a, T> ParRawIter<'a, T> {
-        pub fn slice(&self) -> &'a mut [T] {
+        /// Returns a sequential iterator over the same segments.
+        pub fn iter(self) -> RawIter<'a, T> {
             RawIter {
-                segment: self.segment,
-                buffer: self.buffer,
+                front: self.front,
+                back: self.back,
+                _marker: PhantomData,
             }
-            .next()
-            .unwrap()
         }
     }
 
@@ -292,31 +1916,31 @@ mod rayon {
         type Item = &'a mut [T];
 
         fn split(self) -> (Self, Option<Self>) {
-            let next = unsafe {
-                let ptr = *(&mut *self.segment).next.get_mut();
-                ptr.as_mut()
-            };
-
-            let buffer = self.buffer;
-            match next {
-                Some(next) => (
-                    self,
-                    Some(Self {
-                        buffer,
-                        segment: next as *mut _,
-                    }),
-                ),
-                None => (self, None),
+            if self.front == self.back {
+                return (self, None);
             }
+
+            // Split off everything after the first segment.
+            let next = unsafe { *(&mut *self.front).next.get_mut() };
+            (
+                Self {
+                    front: self.front,
+                    back: self.front,
+                    _marker: PhantomData,
+                },
+                Some(Self {
+                    front: next,
+                    back: self.back,
+                    _marker: PhantomData,
+                }),
+            )
         }
 
         fn fold_with<F>(self, folder: F) -> F
         where
             F: Folder<Self::Item>,
         {
-            let slice = self.slice();
-
-            folder.consume(slice)
+            folder.consume_iter(self.iter())
         }
     }
 }
@@ -344,4 +1968,98 @@ mod tests {
             assert_eq!(unsafe { buffer.pop() }, Some(i));
         }
     }
+
+    #[test]
+    #[cfg(not(feature = "metrics"))]
+    fn metrics_disabled() {
+        assert_eq!(mem::size_of::<Metrics>(), 0);
+    }
+
+    #[test]
+    fn retention() {
+        fn spares<T>(buffer: &mut RawBuffer<T>) -> usize {
+            let mut spares = 0;
+            let mut segment = unsafe { *(&mut **buffer.head.get_mut()).next.get_mut() };
+            while !segment.is_null() {
+                spares += 1;
+                segment = unsafe { *(&mut *segment).next.get_mut() };
+            }
+            spares
+        }
+
+        let mut buffer = RawBuffer::new();
+        buffer.set_retention(RetentionPolicy {
+            max_segments: Some(2),
+            max_bytes: None,
+        });
+
+        for i in 0..100_000 {
+            unsafe { buffer.push(i) };
+        }
+        for _ in 0..100_000 {
+            unsafe { buffer.pop() };
+        }
+        assert!(spares(&mut buffer) <= 2);
+
+        for i in 0..100_000 {
+            unsafe { buffer.push(i) };
+        }
+        buffer.clear();
+        assert!(spares(&mut buffer) <= 2);
+        assert_eq!(unsafe { buffer.pop() }, None);
+
+        buffer.shrink_to_fit();
+        assert_eq!(spares(&mut buffer), 0);
+
+        for i in 0..100_000 {
+            unsafe { buffer.push(i) };
+        }
+        for i in 0..100_000 {
+            assert_eq!(unsafe { buffer.pop() }, Some(i));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn growth_allocates_once() {
+        const THREADS: usize = 8;
+        const ITERATIONS: usize = 1_000_000;
+
+        let mut buffer = RawBuffer::new();
+
+        crossbeam::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|_| {
+                    for x in 0..ITERATIONS / THREADS {
+                        unsafe { buffer.push(x) };
+                    }
+                });
+            }
+        })
+        .unwrap();
+
+        // Count the segments a single producer would need, plus
+        // the one allocated ahead of time.
+        let mut expected = 1;
+        let mut capacity = STARTING_SIZE;
+        let mut total = 0;
+        while total <= ITERATIONS {
+            total += capacity;
+            capacity = min(MAX_SIZE, capacity * 2);
+            expected += 1;
+        }
+
+        let mut allocated = 0;
+        let mut segment = *buffer.tail.get_mut();
+        while !segment.is_null() {
+            allocated += 1;
+            segment = unsafe { *(&mut *segment).next.get_mut() };
+        }
+
+        // Producers which found a segment full before the next one was
+        // linked may have allocated spares of their own.
+        let stats = buffer.metrics().stats();
+        assert_eq!(allocated, stats.segments_allocated);
+        assert!(allocated - stats.wasted_segments <= expected);
+    }
 }
@@ -1,4 +1,5 @@
 use crate::seg_buffer::raw::ParRawIter;
+use crate::seg_buffer::SegmentAllocator;
 use crate::SegBuffer;
 use rayon::iter::plumbing::{Consumer, Folder, UnindexedConsumer, UnindexedProducer};
 use rayon::iter::{plumbing, Flatten};
@@ -20,6 +21,20 @@ where
     }
 }
 
+impl<T, A> ParallelExtend<T> for SegBuffer<T, A>
+where
+    T: Send,
+    A: SegmentAllocator + Sync,
+{
+    fn par_extend<I>(&mut self, par_iter: I)
+    where
+        I: IntoParallelIterator<Item = T>,
+    {
+        let buffer = &*self;
+        par_iter.into_par_iter().for_each(|x| buffer.push(x));
+    }
+}
+
 /// A parallel iterator over slices in a `SegBuffer`.
 pub struct ParSliceIter<'a, T> {
     pub(super) raw: ParRawIter<'a, T>,
@@ -55,9 +70,7 @@ where
     where
         F: Folder<Self::Item>,
     {
-        let slice: &'a [T] = self.raw.slice();
-
-        folder.consume(slice)
+        folder.consume_iter(self.raw.iter().map(|slice| &*slice))
     }
 }
 
@@ -0,0 +1,120 @@
+use crate::seg_buffer::Iter;
+use crate::SegBuffer;
+use std::iter::FlatMap;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+#[cfg(feature = "rayon")]
+use crate::seg_buffer::ParIter;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// Counter used to assign shards to threads round-robin.
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The shard the current thread pushes to, modulo the shard count.
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed);
+}
+
+/// A set of `SegBuffer`s which producers push to depending on
+/// their thread, spreading contention over several counters.
+///
+/// With very many producers, even a single `fetch_add` per push
+/// saturates the `front` counter of the head segment. Each thread is
+/// assigned one shard the first time it pushes, and always pushes to it,
+/// so values pushed by one thread are still kept in FIFO order. There is
+/// no order between values pushed by different threads; `into_ordered`
+/// recovers one from a key stored in the values.
+pub struct ShardedSegBuffer<T> {
+    shards: Box<[SegBuffer<T>]>,
+}
+
+/// An iterator over references to values in a `ShardedSegBuffer`, shard by shard.
+pub type ShardedIter<'a, T> =
+    FlatMap<slice::IterMut<'a, SegBuffer<T>>, Iter<'a, T>, fn(&'a mut SegBuffer<T>) -> Iter<'a, T>>;
+
+/// A parallel iterator over references to values in a `ShardedSegBuffer`.
+#[cfg(feature = "rayon")]
+pub type ShardedParIter<'a, T> = rayon::iter::FlatMap<
+    rayon::slice::IterMut<'a, SegBuffer<T>>,
+    fn(&'a mut SegBuffer<T>) -> ParIter<'a, T>,
+>;
+
+impl<T> Default for ShardedSegBuffer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> ShardedSegBuffer<T> {
+    /// Creates a new, empty `ShardedSegBuffer<T>` with
+    /// one shard per available CPU.
+    pub fn new() -> Self {
+        Self::with_shards(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    /// Creates a new, empty `ShardedSegBuffer<T>` with `shards` shards.
+    ///
+    /// # Panics
+    /// Panics if `shards` is zero.
+    pub fn with_shards(shards: usize) -> Self {
+        assert!(shards > 0, "shard count must be nonzero");
+        Self {
+            shards: (0..shards).map(|_| SegBuffer::new()).collect(),
+        }
+    }
+
+    /// Returns the number of shards.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Pushes an element to the current thread's shard.
+    pub fn push(&self, value: T) {
+        let shard = SHARD.with(|&shard| shard) % self.shards.len();
+        self.shards[shard].push(value);
+    }
+
+    /// Returns the shards of the buffer.
+    pub fn shards_mut(&mut self) -> &mut [SegBuffer<T>] {
+        &mut self.shards
+    }
+
+    /// Returns an iterator over references to values in the buffer.
+    ///
+    /// Values from each shard are yielded in order, one shard after another.
+    pub fn iter(&mut self) -> ShardedIter<'_, T> {
+        self.shards.iter_mut().flat_map(SegBuffer::iter)
+    }
+
+    /// Returns a parallel iterator over references to values in the buffer.
+    #[cfg(feature = "rayon")]
+    pub fn par_iter(&mut self) -> ShardedParIter<'_, T>
+    where
+        T: Send + Sync,
+    {
+        self.shards.par_iter_mut().flat_map(SegBuffer::par_iter)
+    }
+
+    /// Collects the values of all shards into a `Vec`, ordered by `key`.
+    ///
+    /// The sort is stable and each shard is taken in order, so values with
+    /// equal keys keep the order in which each thread pushed them.
+    pub fn into_ordered<K, F>(self, key: F) -> Vec<T>
+    where
+        K: Ord,
+        F: FnMut(&T) -> K,
+    {
+        let mut values = Vec::new();
+        for shard in self.shards.into_vec() {
+            values.append(&mut shard.into_vec());
+        }
+
+        // Each shard is a run which is usually sorted already,
+        // which the merge sort takes advantage of.
+        values.sort_by_key(key);
+        values
+    }
+}
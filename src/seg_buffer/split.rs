@@ -0,0 +1,152 @@
+use crate::seg_buffer::raw::RawBuffer;
+use crate::seg_buffer::sync::epoch;
+use crate::SegBuffer;
+use crossbeam_utils::Backoff;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+struct Shared<T> {
+    raw: RawBuffer<T>,
+    /// Number of live `Sender`s.
+    senders: AtomicUsize,
+}
+
+/// The pushing half of a `SegBuffer`, created by `SegBuffer::split`.
+///
+/// Senders can be cloned and shared freely between threads.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// The popping half of a `SegBuffer`, created by `SegBuffer::split`.
+///
+/// There is exactly one `Receiver` per buffer, so popping does not
+/// need `&mut` access to the buffer itself.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> SegBuffer<T> {
+    /// Splits the buffer into a `Sender`, which pushes values, and a
+    /// `Receiver`, which pops them concurrently.
+    ///
+    /// Values already in the buffer are kept. Like in `SegBufferMpmc`,
+    /// popping while values are being pushed pins the current thread,
+    /// and fully read segments are freed rather than recycled.
+    pub fn split(mut self) -> (Sender<T>, Receiver<T>) {
+        self.raw.skip_unused_slots();
+        let shared = Arc::new(Shared {
+            raw: self.raw,
+            senders: AtomicUsize::new(1),
+        });
+
+        (
+            Sender {
+                shared: Arc::clone(&shared),
+            },
+            Receiver { shared },
+        )
+    }
+}
+
+impl<T> Sender<T> {
+    /// Pushes an element to the buffer.
+    pub fn push(&self, value: T) {
+        let _guard = epoch::pin();
+        unsafe { self.shared.raw.push(value) }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.senders.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Pops an element from the front of the buffer.
+    ///
+    /// Returns `None` if the buffer is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        let guard = epoch::pin();
+        unsafe { self.shared.raw.pop_shared(&guard) }
+    }
+
+    /// Returns whether every `Sender` has been dropped.
+    ///
+    /// Once this returns `true`, every value pushed is visible to `pop`,
+    /// so a following `pop` returning `None` means the buffer is exhausted.
+    pub fn is_disconnected(&self) -> bool {
+        self.shared.senders.load(Ordering::Acquire) == 0
+    }
+
+    /// Returns an iterator which pops the values currently in the buffer.
+    ///
+    /// The iterator ends once the buffer is empty, even if
+    /// senders are still connected.
+    pub fn try_iter(&mut self) -> TryIter<'_, T> {
+        TryIter { receiver: self }
+    }
+
+    /// Returns an iterator which pops every value until the buffer is
+    /// empty and every `Sender` has been dropped, spinning while it waits
+    /// for values.
+    pub fn drain(&mut self) -> Drain<'_, T> {
+        Drain { receiver: self }
+    }
+
+    /// Converts the receiver back into a `SegBuffer` holding the values
+    /// which have not been popped yet.
+    ///
+    /// Returns the receiver unchanged if any `Sender` is still alive.
+    pub fn into_buffer(self) -> Result<SegBuffer<T>, Self> {
+        match Arc::try_unwrap(self.shared) {
+            Ok(shared) => Ok(SegBuffer { raw: shared.raw }),
+            Err(shared) => Err(Self { shared }),
+        }
+    }
+}
+
+/// Iterator returned by `Receiver::try_iter`.
+pub struct TryIter<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<'a, T> Iterator for TryIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.pop()
+    }
+}
+
+/// Iterator returned by `Receiver::drain`.
+pub struct Drain<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<'a, T> Iterator for Drain<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let backoff = Backoff::new();
+        loop {
+            if let Some(value) = self.receiver.pop() {
+                return Some(value);
+            }
+            if self.receiver.is_disconnected() {
+                return self.receiver.pop();
+            }
+            backoff.snooze();
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use crate::seg_buffer::mpmc::SegBufferMpmc;
+use crate::seg_buffer::wake::Waiters;
+use futures_core::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// A variant of `SegBufferMpmc` which is consumed asynchronously
+/// as a `futures_core::Stream`.
+///
+/// `&AsyncSegBuffer<T>` implements `Stream`, so the buffer can be
+/// shared (e.g. in an `Arc`) between producers and one consumer task.
+/// When the consumer finds the buffer empty, it registers its waker
+/// and the next `push` wakes it. Producers only touch the waker when
+/// the consumer is actually waiting; as in `BlockingSegBuffer`, each
+/// push issues one more atomic load to find out.
+///
+/// Only the most recently registered waker is kept, so the buffer is
+/// meant to be polled by a single task at a time. The stream never ends.
+///
+/// Requires the `async` feature.
+pub struct AsyncSegBuffer<T> {
+    buffer: SegBufferMpmc<T>,
+    /// The consumer, while it waits for a value.
+    consumer: Waiters,
+}
+
+impl<T> Default for AsyncSegBuffer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> AsyncSegBuffer<T> {
+    /// Creates a new, empty `AsyncSegBuffer<T>`.
+    pub fn new() -> Self {
+        Self {
+            buffer: SegBufferMpmc::new(),
+            consumer: Waiters::new(),
+        }
+    }
+
+    /// Pushes an element to the buffer, waking the consumer if it is waiting.
+    pub fn push(&self, value: T) {
+        self.buffer.push(value);
+        self.consumer.notify();
+    }
+
+    /// Pops an element from the front of the buffer.
+    ///
+    /// Returns `None` without waiting if the buffer is empty.
+    pub fn pop(&self) -> Option<T> {
+        self.buffer.pop()
+    }
+}
+
+impl<T> Stream for &AsyncSegBuffer<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        if let Some(value) = self.pop() {
+            return Poll::Ready(Some(value));
+        }
+
+        self.consumer.register(cx.waker());
+
+        // A value pushed before registering did not wake us.
+        match self.pop() {
+            Some(value) => {
+                self.consumer.unregister();
+                Poll::Ready(Some(value))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::seg_buffer::raw::RawBuffer;
+use crate::SegBuffer;
+use crossbeam_epoch::{self as epoch, Atomic, Owned};
+use crossbeam_utils::Backoff;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A double-buffered `SegBuffer` whose contents can be taken
+/// while other threads keep pushing.
+///
+/// `take` atomically installs a fresh, empty buffer and returns the old one.
+/// Writers never block: pushes which started before the swap complete into
+/// the old buffer, and `take` waits for them using epoch-based reclamation
+/// before handing it over. Pushes which start after the swap go to the new buffer.
+///
+/// This is useful when many threads produce events and one thread periodically
+/// (e.g. once per frame) processes everything pushed so far.
+pub struct SwapBuffer<T> {
+    current: Atomic<RawBuffer<T>>,
+}
+
+impl<T> Default for SwapBuffer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> SwapBuffer<T> {
+    /// Creates a new, empty `SwapBuffer<T>`.
+    pub fn new() -> Self {
+        Self {
+            current: Atomic::new(RawBuffer::new()),
+        }
+    }
+
+    /// Pushes an element to the current buffer.
+    pub fn push(&self, value: T) {
+        let guard = epoch::pin();
+        let raw = self.current.load(Ordering::Acquire, &guard);
+
+        unsafe { raw.deref().push(value) }
+    }
+
+    /// Replaces the current buffer with an empty one and returns
+    /// everything pushed before the swap.
+    ///
+    /// This function waits until every `push` which may still be writing
+    /// into the old buffer has completed.
+    pub fn take(&self) -> SegBuffer<T> {
+        let guard = epoch::pin();
+        let old = self
+            .current
+            .swap(Owned::new(RawBuffer::new()), Ordering::AcqRel, &guard)
+            .as_raw() as *mut RawBuffer<T>;
+
+        // A deferred function runs only once every thread pinned at the time
+        // of the swap has been unpinned, i.e. once no `push` can still hold
+        // a reference to the old buffer.
+        let done = Arc::new(AtomicBool::new(false));
+        {
+            let done = Arc::clone(&done);
+            guard.defer(move || done.store(true, Ordering::Release));
+        }
+        guard.flush();
+        drop(guard);
+
+        let backoff = Backoff::new();
+        while !done.load(Ordering::Acquire) {
+            epoch::pin().flush();
+            backoff.snooze();
+        }
+
+        let raw = unsafe { *Box::from_raw(old) };
+        SegBuffer { raw }
+    }
+}
+
+impl<T> Drop for SwapBuffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            drop(
+                self.current
+                    .load(Ordering::Relaxed, epoch::unprotected())
+                    .into_owned(),
+            );
+        }
+    }
+}
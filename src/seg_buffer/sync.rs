@@ -0,0 +1,192 @@
+//! Synchronization primitives used by the buffer.
+//!
+//! Building with `RUSTFLAGS="--cfg loom"` replaces them with the
+//! instrumented versions from `loom`, so that `tests/loom.rs` can
+//! explore the interleavings of concurrent operations.
+
+#[cfg(not(loom))]
+pub use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicUsize, Ordering};
+#[cfg(all(feature = "std", not(loom)))]
+pub use crossbeam_epoch as epoch;
+#[cfg(not(loom))]
+pub use crossbeam_utils::Backoff;
+
+/// Tracks accesses to the values in a segment's slots.
+///
+/// Without loom, it is empty and only runs the accesses.
+#[cfg(not(loom))]
+pub struct SlotCells;
+
+#[cfg(not(loom))]
+impl SlotCells {
+    #[inline(always)]
+    pub fn new(_capacity: usize) -> Self {
+        SlotCells
+    }
+
+    /// Runs `f`, which reads from the slots in `range`.
+    #[inline(always)]
+    pub fn with<R>(&self, _range: Range<usize>, f: impl FnOnce() -> R) -> R {
+        f()
+    }
+
+    /// Runs `f`, which writes to or moves out of the slots in `range`.
+    #[inline(always)]
+    pub fn with_mut<R>(&self, _range: Range<usize>, f: impl FnOnce() -> R) -> R {
+        f()
+    }
+}
+
+use core::ops::Range;
+
+#[cfg(loom)]
+pub use self::model::*;
+
+#[cfg(loom)]
+mod model {
+    use super::Range;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use core::cell::Cell;
+    use core::ops::{Deref, DerefMut};
+    use loom::cell::UnsafeCell;
+
+    pub use loom::sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicUsize, Ordering};
+
+    /// Tracks accesses to the values in a segment's slots, so that
+    /// the models catch a value accessed without synchronizing
+    /// with the thread which wrote it.
+    pub struct SlotCells(Box<[UnsafeCell<()>]>);
+
+    impl SlotCells {
+        pub fn new(capacity: usize) -> Self {
+            SlotCells((0..capacity).map(|_| UnsafeCell::new(())).collect())
+        }
+
+        /// Runs `f`, which reads from the slots in `range`.
+        pub fn with<R>(&self, range: Range<usize>, f: impl FnOnce() -> R) -> R {
+            let _reads: Vec<_> = self.0[range].iter().map(UnsafeCell::get).collect();
+            f()
+        }
+
+        /// Runs `f`, which writes to or moves out of the slots in `range`.
+        pub fn with_mut<R>(&self, range: Range<usize>, f: impl FnOnce() -> R) -> R {
+            let _writes: Vec<_> = self.0[range].iter().map(UnsafeCell::get_mut).collect();
+            f()
+        }
+    }
+
+    /// Stands in for `crossbeam_utils::Backoff`. Spin loops
+    /// must yield to the model so that other threads can progress.
+    ///
+    /// It completes after a single snooze, so that the models
+    /// also explore giving up on waiting.
+    pub struct Backoff {
+        snoozed: Cell<bool>,
+    }
+
+    impl Backoff {
+        pub fn new() -> Self {
+            Backoff {
+                snoozed: Cell::new(false),
+            }
+        }
+
+        pub fn snooze(&self) {
+            self.snoozed.set(true);
+            loom::thread::yield_now();
+        }
+
+        pub fn is_completed(&self) -> bool {
+            self.snoozed.get()
+        }
+    }
+
+    /// Stands in for `crossbeam_epoch`, which cannot run under loom.
+    ///
+    /// Deferred functions never run, so segments released by
+    /// consumers are leaked: the models check how values are
+    /// handed over, not when segments are freed.
+    pub mod epoch {
+        use core::mem;
+
+        pub struct Guard;
+
+        impl Guard {
+            pub unsafe fn defer_unchecked<F, R>(&self, f: F)
+            where
+                F: FnOnce() -> R,
+            {
+                mem::forget(f);
+            }
+        }
+
+        pub fn pin() -> Guard {
+            Guard
+        }
+    }
+
+    /// Provides `get_mut` for loom's atomics, which cannot hand
+    /// out a reference to their value.
+    pub trait AtomicMut: Sized {
+        type Value: Copy;
+
+        fn get_mut(&mut self) -> Mut<'_, Self>;
+
+        fn set(&mut self, value: Self::Value);
+    }
+
+    /// The value of an atomic borrowed through `AtomicMut::get_mut`,
+    /// which is written back when dropped.
+    pub struct Mut<'a, A: AtomicMut> {
+        atomic: &'a mut A,
+        value: A::Value,
+    }
+
+    impl<'a, A: AtomicMut> Deref for Mut<'a, A> {
+        type Target = A::Value;
+
+        fn deref(&self) -> &A::Value {
+            &self.value
+        }
+    }
+
+    impl<'a, A: AtomicMut> DerefMut for Mut<'a, A> {
+        fn deref_mut(&mut self) -> &mut A::Value {
+            &mut self.value
+        }
+    }
+
+    impl<'a, A: AtomicMut> Drop for Mut<'a, A> {
+        fn drop(&mut self) {
+            let value = self.value;
+            self.atomic.set(value);
+        }
+    }
+
+    macro_rules! atomic_mut {
+        ($atomic:ty, $value:ty $(, $param:ident)?) => {
+            impl$(<$param>)? AtomicMut for $atomic {
+                type Value = $value;
+
+                fn get_mut(&mut self) -> Mut<'_, Self> {
+                    // No other thread can access the atomic while it is borrowed.
+                    let value = unsafe { self.unsync_load() };
+                    Mut {
+                        atomic: self,
+                        value,
+                    }
+                }
+
+                fn set(&mut self, value: $value) {
+                    self.store(value, Ordering::Relaxed);
+                }
+            }
+        };
+    }
+
+    atomic_mut!(AtomicBool, bool);
+    atomic_mut!(AtomicU8, u8);
+    atomic_mut!(AtomicUsize, usize);
+    atomic_mut!(AtomicPtr<T>, *mut T, T);
+}
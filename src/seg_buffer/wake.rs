@@ -0,0 +1,117 @@
+use crossbeam_utils::Backoff;
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::task::Waker;
+use std::time::Instant;
+
+/// Threads and tasks waiting for another thread to change a buffer,
+/// e.g. to push a value into it or to pop one and make room.
+///
+/// The thread which makes the change with a `SeqCst` read-modify-write
+/// calls `notify` afterwards. That costs a `SeqCst` load of the waiter
+/// count; the lock is only taken if someone is waiting. Waiters register
+/// themselves with a `SeqCst` increment and a fence before checking for
+/// the change one last time, so either they see the change or the
+/// notifier sees them.
+pub struct Waiters {
+    /// Number of parked threads, plus one if a waker is registered.
+    count: AtomicUsize,
+    /// The waker of the task waiting for the change, if any.
+    waker: Mutex<Option<Waker>>,
+    condvar: Condvar,
+}
+
+impl Waiters {
+    pub fn new() -> Self {
+        Self {
+            count: AtomicUsize::new(0),
+            waker: Mutex::new(None),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Wakes one parked thread and the registered task, if there are any.
+    ///
+    /// The change must have been made with a `SeqCst` read-modify-write,
+    /// which orders it before the load of the waiter count.
+    pub fn notify(&self) {
+        if self.count.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+
+        let mut waker = self.waker.lock().unwrap();
+        self.condvar.notify_one();
+        if let Some(waker) = waker.take() {
+            self.count.fetch_sub(1, Ordering::Relaxed);
+            waker.wake();
+        }
+    }
+
+    /// Calls `f` until it returns `Some`, parking the current thread
+    /// between calls once spinning briefly has not helped.
+    ///
+    /// Returns `None` if `deadline` passes first.
+    pub fn wait_until<R, F>(&self, deadline: Option<Instant>, mut f: F) -> Option<R>
+    where
+        F: FnMut() -> Option<R>,
+    {
+        let backoff = Backoff::new();
+        while !backoff.is_completed() {
+            if let Some(result) = f() {
+                return Some(result);
+            }
+            backoff.snooze();
+        }
+
+        let mut lock = self.waker.lock().unwrap();
+        self.count.fetch_add(1, Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+
+        let result = loop {
+            if let Some(result) = f() {
+                break Some(result);
+            }
+
+            // Notifiers must take the lock to wake us, so no wakeup
+            // can be missed between the call above and waiting.
+            lock = match deadline {
+                None => self.condvar.wait(lock).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break None;
+                    }
+                    self.condvar.wait_timeout(lock, deadline - now).unwrap().0
+                }
+            };
+        };
+
+        self.count.fetch_sub(1, Ordering::Relaxed);
+        result
+    }
+
+    /// Registers `waker` to be woken by the next `notify`, replacing
+    /// any waker registered before.
+    ///
+    /// The caller must check for the change again afterwards.
+    #[cfg(feature = "async")]
+    pub fn register(&self, waker: &Waker) {
+        let mut registered = self.waker.lock().unwrap();
+        if registered.is_none() {
+            self.count.fetch_add(1, Ordering::SeqCst);
+        }
+        *registered = Some(waker.clone());
+        drop(registered);
+
+        atomic::fence(Ordering::SeqCst);
+    }
+
+    /// Removes the waker registered by `register`, unless `notify`
+    /// has already taken it.
+    #[cfg(feature = "async")]
+    pub fn unregister(&self) {
+        if self.waker.lock().unwrap().take().is_some() {
+            self.count.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
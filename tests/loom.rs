@@ -0,0 +1,236 @@
+//! Model tests for `SegBuffer`, run with:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --test loom --release
+//! ```
+#![cfg(loom)]
+
+use loom::sync::Arc;
+use loom::thread;
+use ripstruct::{BoundedSegBuffer, SegBuffer, SegBufferMpmc};
+
+/// Builds a buffer whose segments hold `capacity` values.
+fn buffer(capacity: usize) -> SegBuffer<usize> {
+    SegBuffer::builder()
+        .starting_capacity(capacity)
+        .growth_factor(1)
+        .build()
+}
+
+#[test]
+fn concurrent_push() {
+    loom::model(|| {
+        let buffer = Arc::new(buffer(4));
+
+        let handle = {
+            let buffer = Arc::clone(&buffer);
+            thread::spawn(move || buffer.push(1))
+        };
+        buffer.push(2);
+        handle.join().unwrap();
+
+        let mut buffer = Arc::try_unwrap(buffer).ok().unwrap();
+        let mut values: Vec<_> = buffer.iter().copied().collect();
+        values.sort_unstable();
+        assert_eq!(values, [1, 2]);
+    });
+}
+
+#[test]
+fn segment_growth() {
+    loom::model(|| {
+        let buffer = Arc::new(buffer(2));
+
+        let handle = {
+            let buffer = Arc::clone(&buffer);
+            thread::spawn(move || {
+                buffer.push(1);
+                buffer.push(2);
+            })
+        };
+        buffer.push(3);
+        handle.join().unwrap();
+
+        let mut buffer = Arc::try_unwrap(buffer).ok().unwrap();
+        assert_eq!(buffer.iter_slices().count(), 2);
+
+        let mut values: Vec<_> = buffer.iter().copied().collect();
+        assert!(values.iter().position(|&x| x == 1) < values.iter().position(|&x| x == 2));
+        values.sort_unstable();
+        assert_eq!(values, [1, 2, 3]);
+    });
+}
+
+#[test]
+fn snapshot_during_push() {
+    loom::model(|| {
+        let buffer = Arc::new(buffer(2));
+        buffer.push(0);
+
+        // The second push fills the segment and the third moves the head.
+        let handle = {
+            let buffer = Arc::clone(&buffer);
+            thread::spawn(move || {
+                buffer.push(1);
+                buffer.push(2);
+            })
+        };
+        let snapshot: Vec<_> = buffer.snapshot_iter().copied().collect();
+        handle.join().unwrap();
+
+        assert_eq!(snapshot, [0, 1, 2][..snapshot.len()]);
+    });
+}
+
+#[test]
+fn pop_recycles_segments() {
+    loom::model(|| {
+        let buffer = Arc::new(buffer(1));
+
+        let handle = {
+            let buffer = Arc::clone(&buffer);
+            thread::spawn(move || buffer.push(1))
+        };
+        buffer.push(2);
+        handle.join().unwrap();
+
+        let mut buffer = Arc::try_unwrap(buffer).ok().unwrap();
+        let mut values = vec![buffer.pop().unwrap(), buffer.pop().unwrap()];
+        values.sort_unstable();
+        assert_eq!(values, [1, 2]);
+        assert_eq!(buffer.pop(), None);
+
+        // The recycled segments are reused for new values.
+        let buffer = Arc::new(buffer);
+        let handle = {
+            let buffer = Arc::clone(&buffer);
+            thread::spawn(move || buffer.push(3))
+        };
+        buffer.push(4);
+        handle.join().unwrap();
+
+        let mut buffer = Arc::try_unwrap(buffer).ok().unwrap();
+        let mut values = vec![buffer.pop().unwrap(), buffer.pop().unwrap()];
+        values.sort_unstable();
+        assert_eq!(values, [3, 4]);
+        assert_eq!(buffer.pop(), None);
+    });
+}
+
+#[test]
+fn straddling_push_many() {
+    loom::model(|| {
+        // The second segment has room for 4 values, so both runs below
+        // claim past the end of a segment and insert a dedicated one.
+        let buffer = Arc::new(
+            SegBuffer::builder()
+                .starting_capacity(2)
+                .growth_factor(2)
+                .build(),
+        );
+        buffer.push(0);
+
+        let handle = {
+            let buffer = Arc::clone(&buffer);
+            thread::spawn(move || buffer.push_many(2, |i| 1 + i))
+        };
+        buffer.push_many(3, |i| 10 + i);
+        handle.join().unwrap();
+
+        // Move the head onto the segment allocated ahead of time.
+        let mut buffer = Arc::try_unwrap(buffer).ok().unwrap();
+        for x in 100..106 {
+            buffer.push(x);
+        }
+
+        let values: Vec<_> = buffer.iter().copied().collect();
+        assert_eq!(values.len(), 12);
+        let mut popped = Vec::new();
+        while let Some(x) = buffer.pop_back() {
+            popped.push(x);
+        }
+        popped.reverse();
+        assert_eq!(popped, values);
+    });
+}
+
+#[test]
+fn dropped_reservation() {
+    loom::model(|| {
+        let buffer = Arc::new(buffer(4));
+
+        // The producer reserves every free slot in the segment but uses
+        // only one, so the rest are returned or skipped when it is dropped.
+        let handle = {
+            let buffer = Arc::clone(&buffer);
+            thread::spawn(move || buffer.producer().push(1))
+        };
+        buffer.push(2);
+        handle.join().unwrap();
+        buffer.push(3);
+
+        let mut buffer = Arc::try_unwrap(buffer).ok().unwrap();
+        let mut values: Vec<_> = buffer.snapshot_iter().copied().collect();
+        values.sort_unstable();
+        assert_eq!(values, [1, 2, 3]);
+
+        let mut values: Vec<_> = std::iter::from_fn(|| buffer.pop()).collect();
+        values.sort_unstable();
+        assert_eq!(values, [1, 2, 3]);
+    });
+}
+
+/// Pops a value, yielding to the other threads while the buffer is empty.
+fn pop_spin<T>(pop: impl Fn() -> Option<T>) -> T {
+    loop {
+        match pop() {
+            Some(value) => return value,
+            None => thread::yield_now(),
+        }
+    }
+}
+
+#[test]
+fn mpmc_push_pop() {
+    loom::model(|| {
+        let buffer = Arc::new(SegBufferMpmc::new());
+        buffer.push(1);
+
+        let consumer = {
+            let buffer = Arc::clone(&buffer);
+            thread::spawn(move || buffer.pop())
+        };
+        buffer.push(2);
+        let popped = buffer.pop();
+
+        // Each value is popped exactly once, by either thread.
+        let mut values: Vec<_> = vec![consumer.join().unwrap(), popped]
+            .into_iter()
+            .flatten()
+            .chain(std::iter::from_fn(|| buffer.pop()))
+            .collect();
+        values.sort_unstable();
+        assert_eq!(values, [1, 2]);
+    });
+}
+
+#[test]
+fn mpmc_releases_segments() {
+    loom::model(|| {
+        // Every value gets a segment of its own, which the
+        // consumer unlinks and releases once it has read it.
+        let buffer = Arc::new(BoundedSegBuffer::with_max_segments(2, 1));
+
+        let producer = {
+            let buffer = Arc::clone(&buffer);
+            thread::spawn(move || {
+                buffer.try_push(1).unwrap();
+                buffer.try_push(2).unwrap();
+            })
+        };
+        assert_eq!(pop_spin(|| buffer.pop()), 1);
+        assert_eq!(pop_spin(|| buffer.pop()), 2);
+        producer.join().unwrap();
+        assert_eq!(buffer.pop(), None);
+    });
+}
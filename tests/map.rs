@@ -0,0 +1,36 @@
+use crossbeam::epoch;
+#[cfg(feature = "metrics")]
+use ripstruct::MapStats;
+use ripstruct::RashMap;
+
+#[test]
+fn empty() {
+    let map: RashMap<u64, u64> = RashMap::new();
+    assert_eq!(map.len(), 0);
+    assert!(map.is_empty());
+
+    let guard = epoch::pin();
+    for key in 0..100 {
+        assert!(map.get(&key, &guard).is_none());
+    }
+}
+
+#[test]
+#[cfg(feature = "metrics")]
+fn stats() {
+    let map: RashMap<u64, u64> = RashMap::new();
+    assert_eq!(map.stats(), MapStats::default());
+
+    let guard = epoch::pin();
+    for key in 0..100 {
+        map.get(&key, &guard);
+    }
+    assert_eq!(
+        map.stats(),
+        MapStats {
+            len: 0,
+            lookups: 100,
+            probed_groups: 100,
+        }
+    );
+}
@@ -1,7 +1,16 @@
 use crossbeam::scope;
-use ripstruct::SegBuffer;
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "std")]
+use ripstruct::{
+    BlockingSegBuffer, BoundedSegBuffer, SegBufferMpmc, SegmentPool, ShardedSegBuffer, SwapBuffer,
+};
+use ripstruct::{Global, SegBuffer, SegmentAllocator};
+use std::alloc::Layout;
+use std::collections::{HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
 
 const ITERATIONS: usize = 1_000_000;
 
@@ -97,7 +106,7 @@ fn multi_thread() {
 
 #[test]
 fn from_iter() {
-    let mut buffer: SegBuffer<_> = (0..ITERATIONS).into_iter().collect();
+    let mut buffer: SegBuffer<_> = (0..ITERATIONS).collect();
 
     for x in 0..ITERATIONS {
         assert_eq!(buffer.pop(), Some(x));
@@ -146,6 +155,373 @@ fn no_double_drop() {
     );
 }
 
+#[test]
+fn clear() {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    struct Dropped;
+
+    impl Drop for Dropped {
+        fn drop(&mut self) {
+            COUNTER.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let mut buffer = SegBuffer::new();
+
+    for _ in 0..ITERATIONS {
+        buffer.push(Dropped);
+    }
+    for _ in 0..ITERATIONS / 2 {
+        buffer.pop().unwrap();
+    }
+
+    buffer.clear();
+    assert_eq!(COUNTER.load(Ordering::Relaxed), ITERATIONS);
+    assert!(buffer.pop().is_none());
+
+    buffer.shrink_to_fit();
+    buffer.push(Dropped);
+    assert!(buffer.pop().is_some());
+    assert!(buffer.pop().is_none());
+}
+
+#[test]
+fn append() {
+    let mut a: SegBuffer<_> = (0..1000).collect();
+    let mut b: SegBuffer<_> = (1000..2000).collect();
+
+    // Leave some spare segments behind in both buffers.
+    for x in 0..100 {
+        assert_eq!(a.pop(), Some(x));
+        assert_eq!(b.pop(), Some(1000 + x));
+    }
+
+    a.append(&mut b);
+    assert!(b.iter().next().is_none());
+    assert!(a.iter().copied().eq((100..1000).chain(1100..2000)));
+
+    a.push(2000);
+    b.push(0);
+    assert!(b.iter().copied().eq(0..1));
+
+    for x in (100..1000).chain(1100..2001) {
+        assert_eq!(a.pop(), Some(x));
+    }
+    assert_eq!(a.pop(), None);
+
+    let mut empty = SegBuffer::new();
+    a.append(&mut empty);
+    a.push(1);
+    assert_eq!(a.pop(), Some(1));
+}
+
+#[test]
+fn split_off() {
+    fn buffer() -> SegBuffer<usize> {
+        let buffer = SegBuffer::builder().starting_capacity(4).build();
+        for x in 0..100 {
+            buffer.push(x);
+        }
+        buffer
+    }
+
+    // Segments hold 4, 8, 16, 32 and 64 values.
+    for &at in &[0, 1, 4, 5, 12, 20, 28, 60, 61, 99, 100] {
+        let mut a = buffer();
+        let mut b = a.split_off(at);
+        assert!(a.iter().copied().eq(0..at));
+        assert!(b.iter().copied().eq(at..100));
+
+        a.push(100);
+        b.push(100);
+        assert!(a.iter().copied().eq((0..at).chain(100..101)));
+        assert!(b.iter().copied().eq((at..100).chain(100..101)));
+
+        a.append(&mut b);
+        assert!(a.iter().copied().eq((0..at).chain(100..101).chain(at..101)));
+    }
+}
+
+#[test]
+fn retain() {
+    let mut buffer: SegBuffer<_> = (0..1000).collect();
+    for x in 0..10 {
+        assert_eq!(buffer.pop(), Some(x));
+    }
+
+    buffer.retain(|x| x % 3 == 0);
+    assert!(buffer.iter().copied().eq((12..1000).step_by(3)));
+
+    buffer.push(1000);
+    assert_eq!(buffer.pop_back(), Some(1000));
+    assert_eq!(buffer.pop_back(), Some(999));
+
+    buffer.retain(|_| false);
+    assert_eq!(buffer.pop(), None);
+}
+
+#[test]
+fn dedup_by_key() {
+    let mut buffer: SegBuffer<_> = (0..1000).map(|x| x / 7).collect();
+
+    buffer.dedup_by_key(|x| *x);
+    assert!(buffer.iter().copied().eq(0..143));
+
+    buffer.dedup_by_key(|x| *x / 10);
+    assert!(buffer.iter().copied().eq((0..143).step_by(10)));
+}
+
+#[test]
+fn sort_unstable() {
+    let values: Vec<usize> = (0..10_000).map(|x| x * 7919 % 10_007).collect();
+    let mut sorted = values.clone();
+    sorted.sort_unstable();
+
+    let mut buffer: SegBuffer<_> = values.iter().copied().collect();
+    buffer.sort_unstable();
+    assert!(buffer.iter().eq(sorted.iter()));
+
+    // The buffer stays usable at both ends.
+    buffer.push(0);
+    assert_eq!(buffer.pop_back(), Some(0));
+    assert_eq!(buffer.pop_front(), Some(sorted[0]));
+
+    buffer.sort_unstable_by(|a, b| b.cmp(a));
+    assert!(buffer.iter().eq(sorted[1..].iter().rev()));
+}
+
+#[test]
+fn sort_unstable_panic() {
+    fn buffer() -> SegBuffer<usize> {
+        (0..1000).map(|x| x * 7919 % 1000).collect()
+    }
+
+    let mut total = 0;
+    buffer().sort_unstable_by(|a, b| {
+        total += 1;
+        a.cmp(b)
+    });
+
+    // Panic while merging the sorted segments.
+    let mut buffer = buffer();
+    let mut comparisons = 0;
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        buffer.sort_unstable_by(|a, b| {
+            comparisons += 1;
+            if comparisons == total - 500 {
+                panic!("comparison failed");
+            }
+            a.cmp(b)
+        })
+    }));
+    assert!(result.is_err());
+
+    // No value is lost or duplicated.
+    let mut values: Vec<_> = buffer.iter().copied().collect();
+    values.sort_unstable();
+    assert!(values.into_iter().eq(0..1000));
+
+    buffer.sort_unstable();
+    assert!(buffer.iter().copied().eq(0..1000));
+}
+
+#[test]
+fn builder() {
+    let mut buffer = SegBuffer::builder()
+        .starting_capacity(4)
+        .growth_factor(3)
+        .max_capacity(50)
+        .build();
+
+    for x in 0..1000 {
+        buffer.push(x);
+    }
+
+    let lengths: Vec<_> = buffer.iter_slices().map(|slice| slice.len()).collect();
+    assert_eq!(lengths[..5], [4, 12, 36, 50, 50]);
+    assert_eq!(lengths.iter().sum::<usize>(), 1000);
+
+    let mut buffer = SegBuffer::builder().starting_bytes(1024).build();
+    for x in 0u64..1000 {
+        buffer.push(x);
+    }
+    assert!(buffer.iter_slices().next().unwrap().len() < 1024 / 8);
+
+    let mut buffer = SegBuffer::with_capacity(1000);
+    for x in 0..1000 {
+        buffer.push(x);
+    }
+    assert_eq!(buffer.iter_slices().next().unwrap().len(), 1000);
+}
+
+#[test]
+fn allocator() {
+    #[derive(Default)]
+    struct Counting {
+        live: AtomicUsize,
+    }
+
+    unsafe impl SegmentAllocator for Counting {
+        fn allocate(&self, layout: Layout) -> *mut u8 {
+            self.live.fetch_add(1, Ordering::Relaxed);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+            self.live.fetch_sub(1, Ordering::Relaxed);
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    let allocator = Counting::default();
+    let mut buffer = SegBuffer::new_in(&allocator);
+    for x in 0..ITERATIONS {
+        buffer.push(x);
+    }
+    assert!(allocator.live.load(Ordering::Relaxed) > 1);
+
+    buffer.clear();
+    buffer.shrink_to_fit();
+    assert_eq!(allocator.live.load(Ordering::Relaxed), 1);
+
+    drop(buffer);
+    assert_eq!(allocator.live.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn separate_allocators() {
+    /// Only frees memory which it allocated itself.
+    #[derive(Default)]
+    struct Arena {
+        live: Mutex<HashSet<usize>>,
+    }
+
+    unsafe impl SegmentAllocator for Arena {
+        fn allocate(&self, layout: Layout) -> *mut u8 {
+            let ptr = Global.allocate(layout);
+            self.live.lock().unwrap().insert(ptr as usize);
+            ptr
+        }
+
+        unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+            assert!(self.live.lock().unwrap().remove(&(ptr as usize)));
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    let first = Arena::default();
+    let second = Arena::default();
+    let mut a = SegBuffer::new_in(&first);
+    let mut b = SegBuffer::new_in(&second);
+    for x in 0..1000 {
+        a.push(x);
+        b.push(1000 + x);
+    }
+
+    a.append(&mut b);
+    assert!(a.iter().copied().eq(0..2000));
+    assert!(b.iter().next().is_none());
+
+    let mut c = a.split_off(500);
+    assert!(a.iter().copied().eq(0..500));
+    assert!(c.iter().copied().eq(500..2000));
+
+    drop((a, b, c));
+    assert!(first.live.lock().unwrap().is_empty());
+    assert!(second.live.lock().unwrap().is_empty());
+}
+
+#[test]
+fn growth_helping() {
+    /// Stalls the first allocation after the buffer's first segment,
+    /// as if the producer growing the buffer had been preempted.
+    #[derive(Default)]
+    struct Stalling {
+        allocations: AtomicUsize,
+        helped: AtomicBool,
+    }
+
+    unsafe impl SegmentAllocator for Stalling {
+        fn allocate(&self, layout: Layout) -> *mut u8 {
+            if self.allocations.fetch_add(1, Ordering::SeqCst) == 1 {
+                thread::sleep(Duration::from_millis(100));
+                // Other producers should have filled the segment
+                // and allocated the next one themselves meanwhile.
+                let allocations = self.allocations.load(Ordering::SeqCst);
+                self.helped.store(allocations > 2, Ordering::SeqCst);
+            }
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    const THREADS: usize = 4;
+
+    let allocator = Stalling::default();
+    let mut buffer = SegBuffer::builder()
+        .starting_capacity(4)
+        .growth_factor(1)
+        .allocator(&allocator)
+        .build();
+
+    scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|_| {
+                for x in 0..100 {
+                    buffer.push(x);
+                }
+            });
+        }
+    })
+    .unwrap();
+    assert!(allocator.helped.load(Ordering::SeqCst));
+    #[cfg(feature = "metrics")]
+    assert!(buffer.stats().wasted_segments >= 1);
+
+    let mut results = vec![0; 100];
+    while let Some(x) = buffer.pop() {
+        results[x] += 1;
+    }
+    assert!(results.iter().all(|&count| count == THREADS));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn segment_pool() {
+    let pool = SegmentPool::new();
+
+    let buffer = SegBuffer::builder()
+        .starting_capacity(16)
+        .growth_factor(1)
+        .allocator(&pool)
+        .build();
+    for x in 0..160 {
+        buffer.push(x);
+    }
+    drop(buffer);
+    let cached = pool.cached();
+    assert!(cached >= 10);
+
+    let mut buffer = SegBuffer::builder()
+        .starting_capacity(16)
+        .growth_factor(1)
+        .allocator(&pool)
+        .build();
+    for x in 0..160 {
+        buffer.push(x);
+    }
+    assert!(pool.cached() < cached);
+    assert!(buffer.iter().copied().eq(0..160));
+
+    drop(buffer);
+    pool.clear();
+    assert_eq!(pool.cached(), 0);
+}
+
 #[test]
 fn iter() {
     let mut buffer = SegBuffer::new();
@@ -185,6 +561,769 @@ fn iter_mut() {
         .for_each(|(i, x)| assert_eq!(i * 2, *x));
 }
 
+#[test]
+fn iter_rev() {
+    let mut buffer: SegBuffer<_> = (0..1000).collect();
+
+    assert!(buffer.iter().rev().copied().eq((0..1000).rev()));
+    assert!(buffer.iter_mut().rev().map(|x| *x).eq((0..1000).rev()));
+
+    let mut slices = buffer.iter_slices();
+    let first = slices.next().unwrap();
+    let last = slices.next_back().unwrap();
+    let middle: usize = slices.map(|slice| slice.len()).sum();
+    assert_eq!(first.len() + middle + last.len(), 1000);
+    assert_eq!(first[0], 0);
+    assert_eq!(last[last.len() - 1], 999);
+
+    for x in 0..100 {
+        assert_eq!(buffer.pop_front(), Some(x));
+    }
+    let mut iter = buffer.iter();
+    for x in 100..550 {
+        assert_eq!(iter.next(), Some(&x));
+        assert_eq!(iter.next_back(), Some(&(1099 - x)));
+    }
+    assert_eq!(iter.next(), None);
+    assert_eq!(iter.next_back(), None);
+}
+
+#[test]
+fn pop_back() {
+    let mut buffer: SegBuffer<_> = (0..1000).collect();
+
+    for x in (500..1000).rev() {
+        assert_eq!(buffer.pop_back(), Some(x));
+    }
+    for x in 0..250 {
+        assert_eq!(buffer.pop_front(), Some(x));
+    }
+
+    // Pushes continue after the remaining values.
+    for x in 500..600 {
+        buffer.push(x);
+    }
+    assert!(buffer.iter().copied().eq(250..600));
+
+    for x in (250..600).rev() {
+        assert_eq!(buffer.pop_back(), Some(x));
+    }
+    assert_eq!(buffer.pop_back(), None);
+    assert_eq!(buffer.pop_front(), None);
+
+    // Slots left unwritten by a producer are skipped.
+    {
+        let mut producer = buffer.producer();
+        producer.push(0);
+        producer.push(1);
+    }
+    buffer.push(2);
+    assert_eq!(buffer.pop_back(), Some(2));
+    assert_eq!(buffer.pop_back(), Some(1));
+    assert_eq!(buffer.pop_back(), Some(0));
+    assert_eq!(buffer.pop_back(), None);
+}
+
+#[test]
+fn into_vec() {
+    let buffer: SegBuffer<_> = (0..1000).map(|x| x.to_string()).collect();
+    let vec = buffer.into_vec();
+    assert_eq!(vec.len(), 1000);
+    assert!(vec.iter().map(|x| x.parse::<i32>().unwrap()).eq(0..1000));
+
+    // Slots left unwritten by a producer are skipped.
+    let mut buffer = SegBuffer::new();
+    {
+        let mut producer = buffer.producer();
+        producer.push(0);
+        producer.push(1);
+    }
+    buffer.push(2);
+    assert_eq!(buffer.pop_front(), Some(0));
+    assert_eq!(buffer.into_vec(), [1, 2]);
+}
+
+#[test]
+fn copy_to_slice() {
+    let mut buffer: SegBuffer<_> = (0..1000).collect();
+    let mut dst = vec![0; 1000];
+    buffer.copy_to_slice(&mut dst);
+    assert!(dst.iter().copied().eq(0..1000));
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        buffer.copy_to_slice(&mut [0; 999]);
+    }));
+    assert!(result.is_err());
+}
+
+#[test]
+fn extend_from_slice() {
+    let buffer = SegBuffer::new();
+    let values: Vec<_> = (0..1000).collect();
+
+    buffer.extend_from_slice(&values[..10]);
+    buffer.extend_from_slice(&[]);
+    buffer.extend_from_slice(&values[10..]);
+
+    let mut buffer = buffer;
+    assert!(buffer.iter().copied().eq(0..1000));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn io() {
+    use std::io::{Read, Write};
+
+    let buffer = SegBuffer::new();
+    scope(|s| {
+        for thread in 0..threads() {
+            let mut buffer = &buffer;
+            s.spawn(move |_| {
+                let line = format!("{:04}\n", thread);
+                for _ in 0..100 {
+                    buffer.write_all(line.as_bytes()).unwrap();
+                }
+            });
+        }
+    })
+    .unwrap();
+    let mut buffer = buffer;
+
+    // Each write is one contiguous run of bytes.
+    let mut out = Vec::new();
+    let slices = buffer.io_slices();
+    assert!(out.write_vectored(&slices).unwrap() > 0);
+    let mut lines: Vec<_> = out
+        .split(|&b| b == b'\n')
+        .filter(|l| !l.is_empty())
+        .collect();
+    lines.sort_unstable();
+    lines.dedup();
+    assert_eq!(lines.len(), threads());
+
+    let mut read = Vec::new();
+    buffer.read_to_end(&mut read).unwrap();
+    assert_eq!(read, out);
+    assert_eq!(buffer.pop(), None);
+
+    // Reads stop at the end of the buffer and skip unwritten slots.
+    buffer.write_all(b"hello ").unwrap();
+    {
+        let mut producer = buffer.producer();
+        producer.push(b'w');
+    }
+    buffer.write_all(b"orld").unwrap();
+    let mut dst = [0; 4];
+    assert_eq!(buffer.read(&mut dst).unwrap(), 4);
+    assert_eq!(&dst, b"hell");
+    let mut rest = String::new();
+    buffer.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "o world");
+}
+
+#[test]
+fn extend() {
+    let mut buffer = SegBuffer::new();
+    buffer.extend(0..500);
+    buffer.extend((500..1000).collect::<Vec<_>>().iter());
+    assert!(buffer.iter().copied().eq(0..1000));
+}
+
+#[test]
+fn clone() {
+    let mut buffer: SegBuffer<_> = SegBuffer::with_capacity(16);
+    buffer.extend((0..1000).map(|x| x.to_string()));
+    for _ in 0..100 {
+        buffer.pop();
+    }
+    {
+        let mut producer = buffer.producer();
+        producer.push(String::from("hole"));
+    }
+    buffer.push(String::from("end"));
+
+    let mut clone = buffer.clone();
+    assert_eq!(clone, buffer);
+
+    let layout: Vec<_> = buffer.iter_slices().map(<[_]>::len).collect();
+    assert_eq!(
+        clone.iter_slices().map(<[_]>::len).collect::<Vec<_>>(),
+        layout
+    );
+    assert!(clone.iter().eq(buffer.iter()));
+
+    // Pushes continue after the cloned values.
+    clone.push(String::from("after"));
+    assert_eq!(clone.pop_back().as_deref(), Some("after"));
+    assert_eq!(clone.pop_back().as_deref(), Some("end"));
+}
+
+#[test]
+fn clone_panic() {
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(PartialEq, Debug)]
+    struct Bomb(usize);
+
+    impl Clone for Bomb {
+        fn clone(&self) -> Self {
+            assert_ne!(self.0, 500, "boom");
+            Bomb(self.0)
+        }
+    }
+
+    impl Drop for Bomb {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let buffer: SegBuffer<_> = (0..1000).map(Bomb).collect();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| buffer.clone()));
+    assert!(result.is_err());
+    // The 500 values cloned before the panic were dropped.
+    assert_eq!(DROPS.load(Ordering::Relaxed), 500);
+
+    drop(buffer);
+    assert_eq!(DROPS.load(Ordering::Relaxed), 1500);
+}
+
+#[test]
+fn debug_eq_hash() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    fn hash<T: Hash>(value: &T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    let a: SegBuffer<_> = (0..1000).collect();
+    let b = SegBuffer::with_capacity(7);
+    b.push_many(1000, |x| x);
+
+    assert_eq!(a, b);
+    assert_eq!(hash(&a), hash(&b));
+    b.push(1000);
+    assert_ne!(a, b);
+    assert_ne!(hash(&a), hash(&b));
+
+    let c: SegBuffer<_> = (0..3).collect();
+    assert_eq!(format!("{:?}", c), "[0, 1, 2]");
+}
+
+#[test]
+#[cfg(feature = "metrics")]
+fn stats() {
+    let mut buffer = SegBuffer::builder()
+        .starting_capacity(4)
+        .growth_factor(2)
+        .build();
+    assert_eq!(buffer.stats().segments_allocated, 1);
+
+    // Fills segments of 4, 8 and 16 values, and less than
+    // half of the next one, so that it does not grow yet.
+    for x in 0..40 {
+        buffer.push(x);
+    }
+    let stats = buffer.stats();
+    assert_eq!(stats.segments_allocated, 4);
+    assert_eq!(stats.head_retries, 3);
+    assert_eq!(stats.wasted_segments, 0);
+    assert_eq!(stats.segments_recycled, 0);
+
+    while buffer.pop().is_some() {}
+    assert_eq!(buffer.stats().segments_recycled, 3);
+}
+
+#[test]
+fn snapshot_iter() {
+    let buffer = SegBuffer::new();
+
+    scope(|s| {
+        for thread in 0..threads() {
+            let buffer = &buffer;
+            s.spawn(move |_| {
+                for x in 0..ITERATIONS / threads() {
+                    buffer.push((thread, x));
+                }
+            });
+        }
+
+        s.spawn(|_| {
+            for _ in 0..16 {
+                // Values pushed by a single thread must appear in order.
+                let mut last = vec![None; threads()];
+                for &(thread, x) in buffer.snapshot_iter() {
+                    assert!(last[thread] < Some(x));
+                    last[thread] = Some(x);
+                }
+            }
+        });
+    })
+    .unwrap();
+
+    assert_eq!(
+        buffer.snapshot_iter().count(),
+        ITERATIONS / threads() * threads()
+    );
+}
+
+#[test]
+fn producer() {
+    let mut buffer = SegBuffer::new();
+
+    scope(|s| {
+        for thread in 0..threads() {
+            let buffer = &buffer;
+            s.spawn(move |_| {
+                // Drop producers part-way through their reservations
+                // so that the buffer contains holes.
+                let mut x = 0;
+                for chunk in 1.. {
+                    let mut producer = buffer.producer();
+                    for _ in 0..chunk {
+                        if x == ITERATIONS / threads() {
+                            return;
+                        }
+                        producer.push((thread, x));
+                        x += 1;
+                    }
+                }
+            });
+        }
+    })
+    .unwrap();
+
+    assert_eq!(
+        buffer.snapshot_iter().count(),
+        ITERATIONS / threads() * threads()
+    );
+    assert_eq!(buffer.iter().count(), ITERATIONS / threads() * threads());
+
+    let mut next = vec![0; threads()];
+    while let Some((thread, x)) = buffer.pop() {
+        assert_eq!(next[thread], x);
+        next[thread] += 1;
+    }
+
+    assert!(next
+        .into_iter()
+        .all(|count| count == ITERATIONS / threads()));
+}
+
+#[test]
+fn push_many() {
+    let mut buffer = SegBuffer::new();
+
+    scope(|s| {
+        for thread in 0..threads() {
+            let buffer = &buffer;
+            s.spawn(move |_| {
+                for len in 1..200 {
+                    buffer.push_many(len, |i| (thread, len, i));
+                }
+            });
+        }
+    })
+    .unwrap();
+    buffer.push_many(300_000, |i| (0, 300_000, i));
+
+    // Every run is contiguous.
+    let mut previous = None;
+    let mut count = 0;
+    for &(thread, len, i) in buffer.iter() {
+        if i > 0 {
+            assert_eq!(previous, Some((thread, len, i - 1)));
+        }
+        previous = Some((thread, len, i));
+        count += 1;
+    }
+
+    assert_eq!(count, (1..200).sum::<usize>() * threads() + 300_000);
+
+    // Popping from the back yields the same values in reverse.
+    let values: Vec<_> = buffer.iter().copied().collect();
+    let mut popped: Vec<_> = std::iter::from_fn(|| buffer.pop_back()).collect();
+    popped.reverse();
+    assert_eq!(popped, values);
+}
+
+#[test]
+fn push_many_after_pop() {
+    let mut buffer = SegBuffer::new();
+
+    // Leave spare segments linked after the head.
+    for x in 0..1000 {
+        buffer.push(x);
+    }
+    while buffer.pop().is_some() {}
+
+    // The run straddles the end of the head, and the
+    // value pushed after it must not land in a spare.
+    for x in 0..10 {
+        buffer.push(x);
+    }
+    buffer.push_many(100_000, |i| 10 + i);
+    buffer.push(100_010);
+
+    for x in 0..=100_010 {
+        assert_eq!(buffer.pop(), Some(x));
+    }
+    assert_eq!(buffer.pop(), None);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn mpmc() {
+    let buffer = SegBufferMpmc::new();
+    let popped = AtomicUsize::new(0);
+
+    let results = scope(|s| {
+        for _ in 0..threads() {
+            s.spawn(|_| {
+                for x in 0..ITERATIONS / threads() {
+                    buffer.push(x);
+                }
+            });
+        }
+
+        let consumers: Vec<_> = (0..threads())
+            .map(|_| {
+                s.spawn(|_| {
+                    let mut results = vec![];
+                    while popped.load(Ordering::Relaxed) < ITERATIONS / threads() * threads() {
+                        if let Some(x) = buffer.pop() {
+                            popped.fetch_add(1, Ordering::Relaxed);
+                            results.push(x);
+                        }
+                    }
+                    results
+                })
+            })
+            .collect();
+
+        consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .fold(HashMap::new(), |mut results, x| {
+                *results.entry(x).or_insert(0) += 1;
+                results
+            })
+    })
+    .unwrap();
+
+    for x in 0..ITERATIONS / threads() {
+        assert_eq!(results[&x], threads());
+    }
+
+    assert_eq!(buffer.pop(), None);
+    assert_eq!(results.len(), ITERATIONS / threads());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn blocking() {
+    use std::time::{Duration, Instant};
+
+    let buffer = BlockingSegBuffer::new();
+    let per_thread = 10_000;
+
+    let mut results: Vec<usize> = scope(|s| {
+        for thread in 0..threads() {
+            let buffer = &buffer;
+            s.spawn(move |_| {
+                for x in 0..per_thread {
+                    buffer.push(thread * per_thread + x);
+                    if x % 1000 == 0 {
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                }
+            });
+        }
+
+        let consumers: Vec<_> = (0..threads())
+            .map(|_| {
+                s.spawn(|_| {
+                    (0..per_thread)
+                        .map(|_| buffer.pop_blocking())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .collect()
+    })
+    .unwrap();
+
+    results.sort_unstable();
+    assert!(results.into_iter().eq(0..per_thread * threads()));
+
+    let start = Instant::now();
+    assert_eq!(buffer.pop_timeout(Duration::from_millis(20)), None);
+    assert!(start.elapsed() >= Duration::from_millis(20));
+
+    buffer.push(1);
+    assert_eq!(buffer.pop_timeout(Duration::from_secs(10)), Some(1));
+    assert_eq!(buffer.pop(), None);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn bounded() {
+    let buffer = BoundedSegBuffer::with_max_segments(2, 8);
+    assert_eq!(buffer.max_len(), 16);
+
+    for x in 0..16 {
+        assert_eq!(buffer.try_push(x), Ok(()));
+    }
+    assert_eq!(buffer.try_push(16), Err(16));
+    assert_eq!(buffer.len(), 16);
+    assert_eq!(buffer.pop(), Some(0));
+    assert_eq!(buffer.try_push(16), Ok(()));
+    for x in 1..17 {
+        assert_eq!(buffer.pop(), Some(x));
+    }
+    assert!(buffer.is_empty());
+    assert_eq!(buffer.pop(), None);
+
+    // Producers block until the consumer catches up.
+    let buffer = BoundedSegBuffer::new(100);
+    let per_thread = 1000;
+    let mut results: Vec<usize> = scope(|s| {
+        for thread in 0..threads() {
+            let buffer = &buffer;
+            s.spawn(move |_| {
+                for x in 0..per_thread {
+                    buffer.push_blocking(thread * per_thread + x);
+                }
+            });
+        }
+
+        let mut results = Vec::new();
+        while results.len() < per_thread * threads() {
+            assert!(buffer.len() <= 100);
+            if let Some(x) = buffer.pop() {
+                results.push(x);
+            }
+        }
+        results
+    })
+    .unwrap();
+
+    results.sort_unstable();
+    assert!(results.into_iter().eq(0..per_thread * threads()));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn sharded() {
+    let mut buffer = ShardedSegBuffer::with_shards(3);
+    assert_eq!(buffer.shard_count(), 3);
+
+    let per_thread = ITERATIONS / threads();
+    scope(|s| {
+        for thread in 0..threads() {
+            let buffer = &buffer;
+            s.spawn(move |_| {
+                for x in 0..per_thread {
+                    buffer.push((x, thread));
+                }
+            });
+        }
+    })
+    .unwrap();
+
+    assert_eq!(buffer.iter().count(), per_thread * threads());
+
+    // Values pushed by each thread stay in order within their shard.
+    for shard in buffer.shards_mut() {
+        let mut last = HashMap::new();
+        for &(x, thread) in shard.iter() {
+            if let Some(previous) = last.insert(thread, x) {
+                assert!(previous < x);
+            }
+        }
+    }
+
+    let ordered = buffer.into_ordered(|&(x, _)| x);
+    assert_eq!(ordered.len(), per_thread * threads());
+    for (i, chunk) in ordered.chunks(threads()).enumerate() {
+        assert!(chunk.iter().all(|&(x, _)| x == i));
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn split() {
+    fn assert_send_sync<T: Clone + Send + Sync>(_: &T) {}
+
+    let buffer: SegBuffer<_> = (0..10).collect();
+    let (sender, mut receiver) = buffer.split();
+    assert_send_sync(&sender);
+
+    assert!(receiver.try_iter().eq(0..10));
+    assert!(!receiver.is_disconnected());
+
+    let mut results: Vec<usize> = scope(|s| {
+        for thread in 0..threads() {
+            let sender = sender.clone();
+            s.spawn(move |_| {
+                for x in 0..ITERATIONS / threads() {
+                    sender.push(thread * ITERATIONS + x);
+                }
+            });
+        }
+        drop(sender);
+
+        s.spawn(|_| receiver.drain().collect()).join().unwrap()
+    })
+    .unwrap();
+
+    assert!(receiver.is_disconnected());
+    assert_eq!(results.len(), ITERATIONS / threads() * threads());
+    results.sort_unstable();
+    results.dedup();
+    assert_eq!(results.len(), ITERATIONS / threads() * threads());
+
+    let mut buffer = receiver.into_buffer().ok().unwrap();
+    buffer.push(1);
+    assert_eq!(buffer.pop(), Some(1));
+    assert_eq!(buffer.pop(), None);
+
+    // The receiver is handed back while a sender is alive.
+    let (sender, receiver) = buffer.split();
+    let receiver = receiver.into_buffer().err().unwrap();
+    sender.push(2);
+    drop(sender);
+    assert!(receiver.into_buffer().ok().unwrap().iter().eq(&[2]));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn split_reuse() {
+    for refill in 0..200 {
+        let buffer: SegBuffer<_> = (0..100).map(|x| x.to_string()).collect();
+        let (sender, mut receiver) = buffer.split();
+        drop(sender);
+        for x in 0..10 {
+            assert_eq!(receiver.pop(), Some(x.to_string()));
+        }
+
+        // The slots read by the receiver must not look written
+        // once their segment is recycled and claimed again.
+        let mut buffer = receiver.into_buffer().ok().unwrap();
+        assert!(std::iter::from_fn(|| buffer.pop()).eq((10..100).map(|x| x.to_string())));
+        for x in 0..refill {
+            buffer.push(x.to_string());
+        }
+        let mut producer = buffer.producer();
+        producer.push(7777.to_string());
+        assert!(buffer
+            .snapshot_iter()
+            .cloned()
+            .eq((0..refill).chain(Some(7777)).map(|x| x.to_string())));
+        drop(producer);
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn split_partial_segments() {
+    // `append` links segments which are not full.
+    let mut buffer: SegBuffer<_> = (0..10).collect();
+    let mut other: SegBuffer<_> = (10..20).collect();
+    buffer.append(&mut other);
+    let (sender, mut receiver) = buffer.split();
+    drop(sender);
+    assert!(receiver.drain().eq(0..20));
+
+    // `retain` leaves gaps at the end of segments.
+    let mut buffer: SegBuffer<_> = (0..1000).collect();
+    buffer.retain(|x| x % 2 == 0);
+    let (sender, mut receiver) = buffer.split();
+    drop(sender);
+    assert!(receiver.drain().eq((0..1000).step_by(2)));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn swap_buffer() {
+    let buffer = SwapBuffer::new();
+    let finished = AtomicUsize::new(0);
+
+    let mut taken = vec![];
+
+    scope(|s| {
+        for thread in 0..threads() {
+            let buffer = &buffer;
+            let finished = &finished;
+            s.spawn(move |_| {
+                for x in 0..ITERATIONS / threads() {
+                    buffer.push((thread, x));
+                }
+                finished.fetch_add(1, Ordering::Release);
+            });
+        }
+
+        while finished.load(Ordering::Acquire) < threads() {
+            taken.push(buffer.take());
+        }
+    })
+    .unwrap();
+    taken.push(buffer.take());
+
+    // Every value is taken exactly once, and values pushed by
+    // a single thread are taken in order.
+    let mut next = vec![0; threads()];
+    for mut frame in taken {
+        while let Some((thread, x)) = frame.pop() {
+            assert_eq!(next[thread], x);
+            next[thread] += 1;
+        }
+    }
+
+    assert!(next
+        .into_iter()
+        .all(|count| count == ITERATIONS / threads()));
+}
+
+#[test]
+#[cfg(feature = "async")]
+fn async_stream() {
+    use futures::executor::block_on;
+    use futures::stream::StreamExt;
+    use ripstruct::AsyncSegBuffer;
+    use std::time::Duration;
+
+    let buffer = AsyncSegBuffer::new();
+
+    let mut results: Vec<usize> = scope(|s| {
+        for thread in 0..threads() {
+            let buffer = &buffer;
+            s.spawn(move |_| {
+                for x in 0..1000 {
+                    buffer.push(thread * 1000 + x);
+                    if x % 100 == 0 {
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                }
+            });
+        }
+
+        block_on((&buffer).take(threads() * 1000).collect())
+    })
+    .unwrap();
+
+    results.sort_unstable();
+    assert!(results.into_iter().eq(0..threads() * 1000));
+    assert_eq!(buffer.pop(), None);
+}
+
 #[cfg(feature = "rayon")]
 #[cfg_attr(feature = "rayon", test)]
 fn par_iter() {
@@ -204,6 +1343,60 @@ fn par_iter() {
         .for_each(|(i, x)| assert_eq!(i * 2, *x));
 }
 
+#[cfg(feature = "rayon")]
+#[cfg_attr(feature = "rayon", test)]
+fn sharded_par_iter() {
+    use rayon::prelude::*;
+
+    let mut buffer = ShardedSegBuffer::with_shards(4);
+    scope(|s| {
+        for thread in 0..threads() {
+            let buffer = &buffer;
+            s.spawn(move |_| {
+                for x in 0..ITERATIONS / threads() {
+                    buffer.push(thread * ITERATIONS + x);
+                }
+            });
+        }
+    })
+    .unwrap();
+
+    let sum: usize = buffer.par_iter().sum();
+    assert_eq!(sum, buffer.iter().sum());
+}
+
+#[cfg(feature = "rayon")]
+#[cfg_attr(feature = "rayon", test)]
+fn par_extend() {
+    use rayon::prelude::*;
+
+    let mut buffer = SegBuffer::new();
+    buffer.par_extend((0..ITERATIONS).into_par_iter());
+
+    let mut values: Vec<_> = buffer.iter().copied().collect();
+    values.sort_unstable();
+    assert!(values.into_iter().eq(0..ITERATIONS));
+}
+
+#[cfg(feature = "rayon")]
+#[cfg_attr(feature = "rayon", test)]
+fn par_sort_unstable() {
+    let mut buffer: SegBuffer<_> = (0..ITERATIONS).map(|x| x * 7919 % ITERATIONS).collect();
+
+    buffer.par_sort_unstable();
+    assert!(buffer.iter().copied().eq(0..ITERATIONS));
+}
+
+#[cfg(feature = "rayon")]
+#[cfg_attr(feature = "rayon", test)]
+fn par_copy_to_slice() {
+    let mut buffer: SegBuffer<_> = (0..ITERATIONS).collect();
+    let mut dst = vec![0; ITERATIONS];
+
+    buffer.par_copy_to_slice(&mut dst);
+    assert!(dst.iter().copied().eq(0..ITERATIONS));
+}
+
 fn threads() -> usize {
     num_cpus::get()
 }
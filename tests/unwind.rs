@@ -0,0 +1,274 @@
+//! Panic safety of `SegBuffer`, checked by counting drops.
+
+use ripstruct::{Global, SegBuffer, SegmentAllocator};
+use std::alloc::Layout;
+use std::panic::{self, AssertUnwindSafe, RefUnwindSafe, UnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A value which counts its drops, and panics when dropped if `bomb` is set.
+struct Counted {
+    value: usize,
+    bomb: bool,
+    drops: Arc<AtomicUsize>,
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.drops.fetch_add(1, Ordering::Relaxed);
+        if self.bomb {
+            panic!("dropped {}", self.value);
+        }
+    }
+}
+
+/// Returns a buffer holding `len` counted values, where the
+/// values at the `bombs` indices panic when dropped.
+fn buffer(len: usize, bombs: &[usize]) -> (SegBuffer<Counted>, Arc<AtomicUsize>) {
+    let drops = Arc::new(AtomicUsize::new(0));
+    let buffer = (0..len)
+        .map(|value| Counted {
+            value,
+            bomb: bombs.contains(&value),
+            drops: Arc::clone(&drops),
+        })
+        .collect();
+    (buffer, drops)
+}
+
+/// An allocator which panics on the allocation numbered `fail`, counting from zero.
+struct Failing {
+    allocations: AtomicUsize,
+    fail: usize,
+}
+
+impl Failing {
+    fn new(fail: usize) -> Self {
+        Failing {
+            allocations: AtomicUsize::new(0),
+            fail,
+        }
+    }
+}
+
+unsafe impl SegmentAllocator for Failing {
+    fn allocate(&self, layout: Layout) -> *mut u8 {
+        if self.allocations.fetch_add(1, Ordering::Relaxed) == self.fail {
+            panic!("allocation failed");
+        }
+        Global.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        Global.deallocate(ptr, layout)
+    }
+}
+
+#[test]
+fn unwind_safe() {
+    fn assert_unwind_safe<T: UnwindSafe + RefUnwindSafe>() {}
+
+    assert_unwind_safe::<SegBuffer<usize>>();
+}
+
+#[test]
+fn drop_panic() {
+    let (buffer, drops) = buffer(1000, &[10]);
+
+    let result = panic::catch_unwind(move || drop(buffer));
+    assert!(result.is_err());
+    assert_eq!(drops.load(Ordering::Relaxed), 1000);
+}
+
+#[test]
+fn clear_panic() {
+    let (mut buffer, drops) = buffer(1000, &[500]);
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| buffer.clear()));
+    assert!(result.is_err());
+    assert_eq!(drops.load(Ordering::Relaxed), 1000);
+
+    // The buffer is empty and still usable.
+    assert_eq!(buffer.iter().count(), 0);
+    assert!(buffer.pop().is_none());
+    buffer.push(Counted {
+        value: 0,
+        bomb: false,
+        drops: Arc::clone(&drops),
+    });
+    assert_eq!(buffer.iter().count(), 1);
+    drop(buffer);
+    assert_eq!(drops.load(Ordering::Relaxed), 1001);
+}
+
+#[test]
+fn pop_and_push() {
+    let (mut buffer, drops) = buffer(1000, &[]);
+
+    for value in 0..500 {
+        let popped = buffer.pop().unwrap();
+        assert_eq!(popped.value, value);
+    }
+    assert_eq!(drops.load(Ordering::Relaxed), 500);
+
+    // A panic while a value is popped does not affect the buffer.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let _popped = buffer.pop();
+        panic!("after pop");
+    }));
+    assert!(result.is_err());
+    assert_eq!(drops.load(Ordering::Relaxed), 501);
+
+    drop(buffer);
+    assert_eq!(drops.load(Ordering::Relaxed), 1000);
+}
+
+#[test]
+fn push_many_panic() {
+    let (buffer, drops) = buffer(0, &[]);
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        buffer.push_many(100, |value| {
+            assert_ne!(value, 50);
+            Counted {
+                value,
+                bomb: false,
+                drops: Arc::clone(&drops),
+            }
+        })
+    }));
+    assert!(result.is_err());
+
+    let mut buffer = buffer;
+    assert!(buffer.iter().map(|c| c.value).eq(0..50));
+    drop(buffer);
+    assert_eq!(drops.load(Ordering::Relaxed), 50);
+}
+
+#[test]
+fn grow_panic() {
+    // The first allocation is the starting segment. The second happens
+    // when a push crosses the middle of it, and panics.
+    let mut buffer = SegBuffer::builder()
+        .starting_capacity(64)
+        .allocator(Failing::new(1))
+        .build();
+
+    for value in 0..32 {
+        buffer.push(value);
+    }
+    let result = panic::catch_unwind(AssertUnwindSafe(|| buffer.push(32)));
+    assert!(result.is_err());
+
+    // Later pushes allocate the next segment rather than waiting for it.
+    for value in 33..1000 {
+        buffer.push(value);
+    }
+
+    // The slot claimed by the failed push does not end the snapshot.
+    assert!(buffer.snapshot_iter().copied().eq((0..32).chain(33..1000)));
+
+    let popped: Vec<_> = std::iter::from_fn(|| buffer.pop()).collect();
+    assert!(popped.into_iter().eq((0..32).chain(33..1000)));
+}
+
+#[test]
+fn push_many_grow_panic() {
+    let mut buffer = SegBuffer::builder()
+        .starting_capacity(64)
+        .allocator(Failing::new(1))
+        .build();
+
+    for value in 0..20 {
+        buffer.push(value);
+    }
+    let result = panic::catch_unwind(AssertUnwindSafe(|| buffer.push_many(100, |i| 100 + i)));
+    assert!(result.is_err());
+
+    // This call links a dedicated segment after the slots given up by the failed one.
+    buffer.push_many(100, |i| 200 + i);
+    buffer.push(300);
+
+    let expected: Vec<_> = (0..20).chain(200..301).collect();
+    assert!(buffer.snapshot_iter().eq(&expected));
+    let popped: Vec<_> = std::iter::from_fn(|| buffer.pop()).collect();
+    assert_eq!(popped, expected);
+}
+
+#[test]
+fn iter_panic() {
+    let (mut buffer, drops) = buffer(1000, &[]);
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        for counted in buffer.iter_mut() {
+            assert_ne!(counted.value, 700);
+            counted.value += 1000;
+        }
+    }));
+    assert!(result.is_err());
+    assert!(buffer
+        .iter()
+        .map(|c| c.value)
+        .eq((1000..1700).chain(700..1000)));
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        buffer.retain(|counted| {
+            assert_ne!(counted.value, 1500);
+            counted.value % 2 == 0
+        })
+    }));
+    assert!(result.is_err());
+    assert_eq!(drops.load(Ordering::Relaxed), 250);
+
+    drop(buffer);
+    assert_eq!(drops.load(Ordering::Relaxed), 1000);
+}
+
+#[test]
+fn from_iter_panic() {
+    let drops = Arc::new(AtomicUsize::new(0));
+
+    let result = panic::catch_unwind(|| {
+        (0..1000)
+            .map(|value| {
+                assert_ne!(value, 600);
+                Counted {
+                    value,
+                    bomb: false,
+                    drops: Arc::clone(&drops),
+                }
+            })
+            .collect::<SegBuffer<_>>()
+    });
+    assert!(result.is_err());
+    assert_eq!(drops.load(Ordering::Relaxed), 600);
+}
+
+#[cfg(feature = "rayon")]
+#[cfg_attr(feature = "rayon", test)]
+fn par_from_iter_panic() {
+    use rayon::prelude::*;
+
+    let drops = Arc::new(AtomicUsize::new(0));
+    let created = AtomicUsize::new(0);
+
+    let result = panic::catch_unwind(|| {
+        (0..100_000)
+            .into_par_iter()
+            .map(|value| {
+                assert_ne!(value, 60_000);
+                created.fetch_add(1, Ordering::Relaxed);
+                Counted {
+                    value,
+                    bomb: false,
+                    drops: Arc::clone(&drops),
+                }
+            })
+            .collect::<SegBuffer<_>>()
+    });
+    assert!(result.is_err());
+    assert_eq!(
+        drops.load(Ordering::Relaxed),
+        created.load(Ordering::Relaxed)
+    );
+}